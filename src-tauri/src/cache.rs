@@ -0,0 +1,289 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    games::payday2,
+    lib::{archive::ArchiveFormat, storage::StorageLayout},
+    registry::{unix_now, ModRelease},
+};
+
+/// An archive in the [`ArchiveCache`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedArchive {
+    pub mod_id: u32,
+    /// The ModWorkshop id of the uploaded file, `0` if ModWorkshop didn't tell us
+    pub file_id: u32,
+    /// SHA-256 of the archive, as lowercase hex
+    pub sha256: String,
+    pub ext: String,
+    /// The name and release of the mod when the archive was downloaded, so it can be installed offline
+    pub name: String,
+    #[serde(flatten)]
+    pub release: ModRelease,
+    pub size: u64,
+    /// Unix timestamp (seconds) of when the archive was downloaded
+    pub cached_at: u64,
+    /// Unix timestamp (seconds) of when the archive was last installed
+    pub last_used: u64,
+}
+
+impl CachedArchive {
+    /// The name of the archive in the cache directory, `{mod_id}-{file_id}-{sha256}.{ext}`
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}-{}-{}.{}",
+            self.mod_id, self.file_id, self.sha256, self.ext
+        )
+    }
+}
+
+/// What [`ArchiveCache::prune`] removed
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PruneReport {
+    pub removed: usize,
+    pub freed: u64,
+}
+
+/// Downloaded archives, kept so mods can be reinstalled (or switched to in a profile) without
+/// downloading them again.
+///
+/// Archives are stored in [`StorageLayout::cache_dir`] under a name made of the mod id, file id
+/// and hash, and listed in `index.json` next to them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchiveCache {
+    #[serde(skip)]
+    dir: PathBuf,
+    pub archives: Vec<CachedArchive>,
+}
+
+impl ArchiveCache {
+    pub async fn load(game: &str) -> Result<Self, String> {
+        Self::load_from(StorageLayout::load(game).await?.cache_dir())
+    }
+
+    pub fn load_from(dir: PathBuf) -> Result<Self, String> {
+        let index = dir.join("index.json");
+        if !index.exists() {
+            return Ok(ArchiveCache {
+                dir,
+                archives: Vec::new(),
+            });
+        }
+
+        let data = fs::read_to_string(&index).map_err(|e| e.to_string())?;
+        let mut cache: ArchiveCache = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+        cache.dir = dir;
+
+        // Forget archives that were deleted behind our back
+        cache
+            .archives
+            .retain(|a| cache.dir.join(a.file_name()).exists());
+        Ok(cache)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        debug!("Saving archive cache index...");
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(self.dir.join("index.json"), data).map_err(|e| e.to_string())
+    }
+
+    pub fn path_of(&self, archive: &CachedArchive) -> PathBuf {
+        self.dir.join(archive.file_name())
+    }
+
+    /// Finds the archive of a specific upload of a mod
+    pub fn find(&self, mod_id: u32, file_id: u32) -> Option<&CachedArchive> {
+        self.archives
+            .iter()
+            .filter(|a| a.mod_id == mod_id && a.file_id == file_id)
+            .max_by_key(|a| a.cached_at)
+    }
+
+    /// Finds the most recently downloaded archive of a mod
+    pub fn latest(&self, mod_id: u32) -> Option<&CachedArchive> {
+        self.archives
+            .iter()
+            .filter(|a| a.mod_id == mod_id)
+            .max_by_key(|a| a.cached_at)
+    }
+
+    /// Moves a freshly downloaded (and verified) archive into the cache, `sha256` is its hash.
+    ///
+    /// An archive with the same mod id, file id and hash replaces the cached one.
+    pub fn add(
+        &mut self,
+        file: &Path,
+        sha256: String,
+        mod_id: u32,
+        file_id: u32,
+        name: String,
+        release: ModRelease,
+    ) -> io::Result<CachedArchive> {
+        let now = unix_now();
+        let archive = CachedArchive {
+            mod_id,
+            file_id,
+            sha256,
+            ext: match ArchiveFormat::detect(file) {
+                Some(format) => format.extension().to_string(),
+                None => file
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("?")
+                    .to_string(),
+            },
+            name,
+            release,
+            size: fs::metadata(file)?.len(),
+            cached_at: now,
+            last_used: now,
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        fs::rename(file, self.path_of(&archive))?;
+
+        self.archives
+            .retain(|a| a.file_name() != archive.file_name());
+        self.archives.push(archive.clone());
+        info!("Cached {} as {}", file.display(), archive.file_name());
+        Ok(archive)
+    }
+
+    /// Checks that a cached archive still matches its hash, removing it from the cache if it doesn't.
+    /// Marks the archive as used if it does.
+    pub fn verify(&mut self, archive: &CachedArchive) -> bool {
+        let path = self.path_of(archive);
+        match sha256_file(&path) {
+            Ok(hash) if hash == archive.sha256 => {
+                if let Some(cached) = self.archives.iter_mut().find(|a| *a == archive) {
+                    cached.last_used = unix_now();
+                }
+                true
+            }
+            result => {
+                warn!(
+                    "Cached archive {} is damaged ({:?}), removing it",
+                    archive.file_name(),
+                    result.err()
+                );
+                let _ = fs::remove_file(&path);
+                self.archives.retain(|a| a != archive);
+                false
+            }
+        }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.archives.iter().map(|a| a.size).sum()
+    }
+
+    /// Removes archives that haven't been used in `max_age` seconds, then the least recently used
+    /// archives until the cache is no bigger than `max_size` bytes.
+    pub fn prune(&mut self, max_age: Option<u64>, max_size: Option<u64>) -> PruneReport {
+        let mut report = PruneReport::default();
+        let now = unix_now();
+
+        // Least recently used first
+        self.archives.sort_by_key(|a| a.last_used);
+        let mut total = self.total_size();
+
+        let mut kept = Vec::new();
+        for archive in std::mem::take(&mut self.archives) {
+            let too_old = max_age.is_some_and(|age| now.saturating_sub(archive.last_used) > age);
+            let too_big = max_size.is_some_and(|size| total > size);
+
+            if !(too_old || too_big) {
+                kept.push(archive);
+                continue;
+            }
+
+            match fs::remove_file(self.path_of(&archive)) {
+                Ok(()) => {
+                    debug!("Pruned {}", archive.file_name());
+                    total -= archive.size;
+                    report.removed += 1;
+                    report.freed += archive.size;
+                }
+                Err(e) => {
+                    warn!("Failed to prune {}: {e}", archive.file_name());
+                    kept.push(archive);
+                }
+            }
+        }
+
+        self.archives = kept;
+        report
+    }
+}
+
+/// Hashes a file with SHA-256, returning it as lowercase hex
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    digest_file::<Sha256>(path)
+}
+
+/// Hashes a file with `D`, returning it as lowercase hex
+pub fn digest_file<D: Digest>(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// The contents of the archive cache, as returned by `get_cache_info`
+#[derive(Serialize, Debug)]
+pub struct CacheInfo {
+    pub archives: Vec<CachedArchive>,
+    pub total_size: u64,
+}
+
+#[tauri::command]
+pub async fn get_cache_info(game: Option<String>) -> Result<CacheInfo, String> {
+    let cache = ArchiveCache::load(game.as_deref().unwrap_or(payday2::GAME_KEY)).await?;
+    Ok(CacheInfo {
+        total_size: cache.total_size(),
+        archives: cache.archives,
+    })
+}
+
+/// Removes cached archives not used in `max_age_days`, then the least recently used ones until
+/// the cache fits in `max_size` bytes
+#[tauri::command]
+pub async fn prune_cache(
+    max_age_days: Option<u64>,
+    max_size: Option<u64>,
+    game: Option<String>,
+) -> Result<PruneReport, String> {
+    let mut cache = ArchiveCache::load(game.as_deref().unwrap_or(payday2::GAME_KEY)).await?;
+    let report = cache.prune(
+        max_age_days.map(|days| days.saturating_mul(86_400)),
+        max_size,
+    );
+    cache.save()?;
+
+    info!(
+        "Pruned {} archives, freed {} bytes",
+        report.removed, report.freed
+    );
+    Ok(report)
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    games::payday2,
+    lib::layout::InstallLayout,
+    registry::{InstallTarget, ModRegistry},
+};
+
+/// What two mods fight over
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(tag = "kind", content = "path", rename_all = "snake_case")]
+pub enum ConflictKey {
+    /// A game asset overridden by more than one mod_overrides root, relative to the roots
+    /// (e.g. `units/pd2_dlc_some/texture.texture`)
+    Asset(PathBuf),
+    /// A file in the game directory, relative to it (e.g. `mods/base/mod.txt`)
+    File(PathBuf),
+}
+
+impl ConflictKey {
+    /// What `file` (in the root at `root_dir`) conflicts on, `None` if it can't conflict.
+    ///
+    /// Only files in subdirectories of a mod_overrides root override game assets, files next to
+    /// `main.xml` (readmes and such) don't.
+    fn of(target: InstallTarget, root_dir: &Path, file: &Path) -> Option<Self> {
+        match target {
+            InstallTarget::ModOverrides => {
+                let asset = file.strip_prefix(root_dir).ok()?;
+                (asset.components().count() > 1).then(|| ConflictKey::Asset(asset.to_path_buf()))
+            }
+            InstallTarget::Mods => {
+                let relative = file.strip_prefix(root_dir.parent()?).ok()?;
+                Some(ConflictKey::File(Path::new("mods").join(relative)))
+            }
+            InstallTarget::Game => {
+                let relative = file.strip_prefix(root_dir.parent()?).ok()?;
+                Some(ConflictKey::File(relative.to_path_buf()))
+            }
+        }
+    }
+}
+
+/// A path that more than one mod writes to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileConflict {
+    #[serde(flatten)]
+    pub key: ConflictKey,
+    /// The ModWorkshop ids of the mods writing it
+    pub mods: Vec<u32>,
+}
+
+/// Which enabled mods write which paths, built from the install manifests in the registry.
+///
+/// Files a mod overwrote count as written by it, so a file that a later mod replaced shows up
+/// as a conflict between the two. Disabled mods are left out, their files aren't in the game
+/// directory.
+#[derive(Debug, Default)]
+pub struct ConflictIndex {
+    /// The mods writing each path, and where their copy of it is installed
+    writers: BTreeMap<ConflictKey, BTreeMap<u32, PathBuf>>,
+}
+
+impl ConflictIndex {
+    pub fn build(registry: &ModRegistry) -> Self {
+        let mut index = ConflictIndex::default();
+
+        for installed in registry.mods.values().filter(|m| m.is_enabled()) {
+            let roots = installed.roots();
+            let overwritten = installed.overwrote.iter().map(|o| &o.path);
+            for file in installed
+                .files
+                .iter()
+                .chain(overwritten)
+                .filter(|f| !f.is_dir())
+            {
+                let key = roots
+                    .iter()
+                    .filter(|root| file.starts_with(&root.install_dir))
+                    .find_map(|root| ConflictKey::of(root.target, &root.install_dir, file));
+
+                if let Some(key) = key {
+                    index
+                        .writers
+                        .entry(key)
+                        .or_default()
+                        .entry(installed.id)
+                        .or_insert_with(|| file.clone());
+                }
+            }
+        }
+
+        debug!("Conflict index covers {} paths", index.writers.len());
+        index
+    }
+
+    /// Every path written by more than one mod
+    pub fn conflicts(&self) -> Vec<FileConflict> {
+        self.writers
+            .iter()
+            .filter(|(_, mods)| mods.len() > 1)
+            .map(|(key, mods)| FileConflict {
+                key: key.clone(),
+                mods: mods.keys().copied().collect(),
+            })
+            .collect()
+    }
+
+    /// Every asset overridden by more than one mod, with where each mod's copy is installed
+    pub fn asset_conflicts(&self) -> impl Iterator<Item = (&ConflictKey, &BTreeMap<u32, PathBuf>)> {
+        self.writers
+            .iter()
+            .filter(|(key, mods)| matches!(key, ConflictKey::Asset(_)) && mods.len() > 1)
+    }
+
+    /// The paths that installing `layout` as mod `mod_id` would write, that other mods already write.
+    ///
+    /// The mod's own files don't count, so updating a mod doesn't conflict with itself.
+    pub fn check(&self, mod_id: u32, layout: &InstallLayout) -> Vec<FileConflict> {
+        let keys: BTreeSet<ConflictKey> = layout
+            .roots
+            .iter()
+            .flat_map(|root| {
+                root.files.iter().filter_map(|file| {
+                    ConflictKey::of(root.target, &root.install_dir, &file.destination)
+                })
+            })
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let others: Vec<u32> = self
+                    .writers
+                    .get(&key)?
+                    .keys()
+                    .copied()
+                    .filter(|id| *id != mod_id)
+                    .collect();
+                if others.is_empty() {
+                    return None;
+                }
+
+                let mut mods = others;
+                mods.push(mod_id);
+                mods.sort_unstable();
+                Some(FileConflict { key, mods })
+            })
+            .collect()
+    }
+}
+
+/// Lists every path that more than one installed (and enabled) mod writes
+#[tauri::command]
+pub async fn get_conflicts(game: Option<String>) -> Result<Vec<FileConflict>, String> {
+    let registry = ModRegistry::load(game.as_deref().unwrap_or(payday2::GAME_KEY))?;
+    Ok(ConflictIndex::build(&registry).conflicts())
+}
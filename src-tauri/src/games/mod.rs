@@ -1,7 +1,25 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    lib::{error::InstallError, events::TaskEmitter},
+    registry::ModRelease,
+};
+
 pub mod payday2; // Module for PAYDAY 2
 
+/// The keys of every game we support, as used in paths and the mod registry
+pub const GAME_KEYS: [&str; 1] = [payday2::GAME_KEY];
+
+/// Fails if `game` isn't one of [`GAME_KEYS`]. Game keys come from the front end and end up in
+/// file names, so anything else (e.g. `../../x`) is rejected before it gets near the disk.
+pub fn check_game_key(game: &str) -> Result<(), String> {
+    if GAME_KEYS.contains(&game) {
+        Ok(())
+    } else {
+        Err(format!("Unknown game \"{game}\""))
+    }
+}
+
 // This is the universal mod structure, all mods should be able to be represented by this
 // Future: Possibly add the option for mods to add additional data, without being explicity defined in the struct
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -31,11 +49,25 @@ pub struct ModWithMeta {
     mod_meta: ModPageMetaData,
 }
 
+/// An installed mod that has a newer release available
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModUpdate {
+    pub id: u32,
+    pub name: String,
+    pub installed: ModRelease,
+    pub latest: ModRelease,
+}
+
 // Trait for a generic game mod API
 // TODO: Make these values more Universal
 pub trait GameModAPI {
     async fn fetch_mods(&self, query: Option<String>) -> Vec<ModWithMeta>;
     async fn get_mod_download_information(&self, id: u32) -> Result<Option<String>, String>;
-    async fn impl_download_mod_from_id(&self, id: u32, window: tauri::Window)
-        -> Result<(), String>;
+    async fn impl_download_mod_from_id(
+        &self,
+        id: u32,
+        task: &TaskEmitter,
+    ) -> Result<(), InstallError>;
+    async fn impl_update_mod(&self, id: u32, task: &TaskEmitter) -> Result<(), InstallError>;
+    async fn impl_check_for_updates(&self) -> Result<Vec<ModUpdate>, String>;
 }
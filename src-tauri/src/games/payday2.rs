@@ -1,25 +1,60 @@
 use std::{
-    collections::HashMap,
-    fs::{create_dir_all, File},
-    io::{self, copy, Cursor},
+    collections::BTreeMap,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use futures::future::BoxFuture;
 use serde_json::json;
-use tokio::fs;
 
 use log::{debug, error, info, trace, warn};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use zip::ZipArchive;
 
-use crate::{games::ModPageMetaData, lib::unzip_mod, settings::load_settings};
+use crate::{
+    cache::{ArchiveCache, CachedArchive},
+    conflicts::{ConflictIndex, FileConflict},
+    games::ModPageMetaData,
+    lib::{
+        archive::ArchiveFormat,
+        dependencies::{install_order, missing_requirements, staged_requirements, Requirement},
+        download::download_to_file,
+        error::InstallError,
+        events::{TaskEmitter, TaskKind},
+        install_staged_mod,
+        integrity::{verify_file, ExpectedFile},
+        layout::InstallLayout,
+        plan::InstallPlan,
+        priority::redeploy,
+        stage_mod,
+        storage::StorageLayout,
+        update::replace_installed_mod,
+    },
+    queue::TaskQueue,
+    registry::{InstalledMod, ModRegistry, ModRelease},
+};
+
+use super::{GameModAPI, Mod, ModUpdate, ModWithMeta};
+
+/// The key used for PAYDAY 2 in paths and the mod registry
+pub const GAME_KEY: &str = "pd2";
+
+/// The Steam app id of PAYDAY 2
+pub const APP_ID: u32 = 218620;
+
+/// How many mods we ask ModWorkshop about in a single request when checking for updates
+const UPDATE_CHECK_BATCH_SIZE: usize = 50;
 
-use super::{GameModAPI, Mod, ModWithMeta};
 pub struct Payday2API;
 
+/// Finds the PAYDAY 2 install directory through Steam
+pub fn game_dir() -> Result<PathBuf, InstallError> {
+    let mut steam_dir = steamlocate::SteamDir::locate().ok_or(InstallError::SteamNotFound)?;
+    let app = steam_dir
+        .app(&APP_ID)
+        .ok_or(InstallError::GameNotInstalled(APP_ID))?;
+
+    Ok(app.path.clone())
+}
+
 // TODO: Remove redundant traits
 #[derive(Deserialize, Serialize, Debug)]
 struct Thumbnail {
@@ -51,6 +86,8 @@ pub struct Payday2Mod {
     has_download: bool,
     download_type: Option<String>,
     thumbnail: Option<Thumbnail>,
+    #[serde(flatten)]
+    release: ModRelease,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -72,13 +109,21 @@ struct Payday2ModMeta {
 #[derive(Deserialize, Serialize, Debug)]
 struct ModDownloadAPIResponse {
     name: String,
+    #[serde(flatten)]
+    release: ModRelease,
     download: Option<ModDownloadData>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 struct ModDownloadData {
+    /// The id of the uploaded file, changes with every upload
+    id: Option<u32>,
     #[serde(rename = "download_url")]
     download_url: Option<String>,
+    /// The size of the uploaded file in bytes
+    size: Option<u64>,
+    /// A hash of the uploaded file, not every file has one
+    hash: Option<String>,
 }
 
 impl GameModAPI for Payday2API {
@@ -161,35 +206,12 @@ impl GameModAPI for Payday2API {
 
     // TODO: Write docstrings
     async fn get_mod_download_information(&self, id: u32) -> Result<Option<String>, String> {
-        info!("Getting mod information...");
-        let response = match reqwest::get(format!("https://api.modworkshop.net/mods/{}", id)).await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                error!("Request failed: {e}");
-                return Ok(None);
-            }
-        };
-
-        // Get the text (Body) from the response
-        let text = match response.text().await {
-            Ok(body) => body,
-            Err(e) => {
-                error!("Failed to read response text: {e}");
-                return Ok(None);
-            }
-        };
-
-        let parsed_response: Result<ModDownloadAPIResponse, serde_json::Error> =
-            serde_json::from_str(&text);
-        match parsed_response {
-            Ok(parsed) => {
-                println!("Parsed");
-
-                if let Some(download_data) = parsed.download {
-                    if let Some(url) = download_data.download_url {
+        match self.fetch_mod_details(id).await {
+            Some(parsed) => {
+                if let Some(download_data) = &parsed.download {
+                    if let Some(url) = &download_data.download_url {
                         info!("parsed.download_url is OK");
-                        return Ok(Some(url));
+                        return Ok(Some(url.clone()));
                     } else {
                         error!("No URL.");
                         trace!("Debug parsed data: {:#?}", &download_data);
@@ -201,106 +223,612 @@ impl GameModAPI for Payday2API {
                     return Ok(None);
                 }
             }
-            Err(e) => {
-                error!("Failed to parse JSON: {:#?}", e);
-                return Ok(None);
-            }
+            None => Ok(None),
         }
     }
 
+    /// Downloads and installs mod `id`. The started/done events are sent by the queue running the task.
     async fn impl_download_mod_from_id(
         &self,
         id: u32,
-        window: tauri::Window,
-    ) -> Result<(), String> {
+        task: &TaskEmitter,
+    ) -> Result<(), InstallError> {
         debug!("Called!");
 
-        // Get the download link
-        let download_location = match self.get_mod_download_information(id).await {
-            Ok(Some(url)) => url,
-            Ok(None) => {
-                error!("No download URL found on id: {id}");
-                return Ok(());
+        if let Err(e) = self.install_mod(id, task).await {
+            error!("Failed to install mod {id}: {e}");
+            return Err(e);
+        }
+
+        info!("Mod downloaded successfully!");
+
+        return Ok(());
+    }
+
+    /// Downloads the latest release of an installed mod and swaps it in place of the installed one,
+    /// keeping any config the user (or the mod) wrote since it was installed.
+    async fn impl_update_mod(&self, id: u32, task: &TaskEmitter) -> Result<(), InstallError> {
+        if let Err(e) = self.update_installed_mod(id, task).await {
+            error!("Failed to update mod {id}: {e}");
+            return Err(e);
+        }
+
+        info!("Mod updated successfully!");
+        Ok(())
+    }
+
+    /// Asks ModWorkshop for the latest release of every installed mod, and returns the ones
+    /// that have a newer release than what is installed.
+    async fn impl_check_for_updates(&self) -> Result<Vec<ModUpdate>, String> {
+        let registry = ModRegistry::load(GAME_KEY)?;
+        let ids: Vec<u32> = registry.mods.keys().copied().collect();
+        info!("Checking {} installed mods for updates", ids.len());
+
+        let client = Client::new();
+        let mut updates = Vec::new();
+
+        for batch in ids.chunks(UPDATE_CHECK_BATCH_SIZE) {
+            // GET bodies are easily dropped on the way, so the filter goes in the URL
+            let mut query: Vec<(&str, String)> =
+                batch.iter().map(|id| ("ids[]", id.to_string())).collect();
+            query.push(("limit", batch.len().to_string()));
+
+            let response = client
+                .get("https://api.modworkshop.net/mods")
+                .header("Accept", "application/json")
+                .query(&query)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to check for updates: {e}"))?;
+
+            let text = response
+                .text()
+                .await
+                .map_err(|e| format!("Failed to read response: {e}"))?;
+
+            let parsed: APIResponse = serde_json::from_str(&text).map_err(|e| {
+                trace!("{:#?}", &text);
+                format!("Failed to parse JSON: {e}")
+            })?;
+
+            // Mods we didn't ask for mean the filter was ignored, and the page is of any mods
+            if let Some(unexpected) = parsed.data.iter().find(|m| !batch.contains(&m.id)) {
+                return Err(format!(
+                    "Failed to check for updates: ModWorkshop returned mod {}, which wasn't asked for",
+                    unexpected.id
+                ));
             }
-            Err(e) => {
-                error!("An error occurred while trying to download: {e}");
-                return Ok(());
+
+            for latest in parsed.data {
+                let Some(installed) = registry.get(latest.id) else {
+                    continue;
+                };
+
+                if latest.release.is_newer_than(&installed.release) {
+                    debug!(
+                        "Update available for {} ({:?} -> {:?})",
+                        installed.name, installed.release.version, latest.release.version
+                    );
+                    updates.push(ModUpdate {
+                        id: latest.id,
+                        name: latest.name,
+                        installed: installed.release.clone(),
+                        latest: latest.release,
+                    });
+                }
             }
+        }
+
+        info!("{} mods have updates", updates.len());
+        Ok(updates)
+    }
+}
+
+/// An archive that was downloaded (or taken from the cache) by [`Payday2API::download_archive`]
+struct DownloadedArchive {
+    name: String,
+    release: ModRelease,
+    path: PathBuf,
+    ext: String,
+    sha256: String,
+}
+
+impl DownloadedArchive {
+    fn from_cache(cache: &ArchiveCache, cached: CachedArchive) -> Self {
+        DownloadedArchive {
+            path: cache.path_of(&cached),
+            name: cached.name,
+            release: cached.release,
+            ext: cached.ext,
+            sha256: cached.sha256,
+        }
+    }
+
+    /// Makes sure we can extract the archive, and that it's complete
+    fn check(&self) -> Result<(), InstallError> {
+        let Some(format) = ArchiveFormat::detect(&self.path) else {
+            warn!("Unable to unzip file, filetype not supported.");
+            return Err(InstallError::UnsupportedArchive(self.ext.clone()));
         };
 
-        debug!("Download URL => {download_location}");
+        format.validate(&self.path)
+    }
+}
 
-        // Update the front end
-        debug!("Emitting `mod_download_started` for {id}");
-        let _ = window.emit("mod_download_started", id);
+impl Payday2API {
+    /// Copies the most recently cached archive of mod `id` into the game without going online.
+    ///
+    /// The mod isn't registered and no conflict priorities are deployed, that's left to the
+    /// caller (see [`apply_profile`](crate::profiles::apply_profile)).
+    ///
+    /// # Returns
+    ///
+    /// The registry entry of the mod, `None` if there is no (intact) archive of it in the cache.
+    pub async fn install_from_cache(&self, id: u32) -> Result<Option<InstalledMod>, InstallError> {
+        let mut cache = ArchiveCache::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Cache)?;
+        let latest = cache.latest(id).cloned();
+        let Some(archive) = self.take_from_cache(&mut cache, latest)? else {
+            return Ok(None);
+        };
 
-        let ext = Path::new(&download_location)
-            .extension()
-            .and_then(|ex| ex.to_str())
-            .unwrap_or("?");
+        info!("Installing mod {id} from the cache");
+        self.copy_archive(id, archive, None).await.map(Some)
+    }
 
-        debug!("Got extension {ext}");
+    /// Downloads (or takes from the cache) the archive of mod `id` and works out what installing it
+    /// would do, without touching the game directory.
+    ///
+    /// The archive is left extracted in the staging directory along with the plan, so the install
+    /// that follows executes this exact plan.
+    pub async fn plan_install(
+        &self,
+        id: u32,
+        task: &TaskEmitter,
+    ) -> Result<InstallPlan, InstallError> {
+        let archive = self.download_archive(id, task).await?;
+        archive.check()?;
+
+        let (staged, layout) = stage_mod(&archive.path, id).await?;
+        let conflicts = self.report_conflicts(id, &layout, None)?;
+        let missing = self.report_missing_dependencies(id, &staged, &layout, None)?;
+        let plan = InstallPlan::new(
+            id,
+            archive.name,
+            archive.release,
+            archive.sha256,
+            layout,
+            conflicts,
+            missing,
+        );
+
+        let plan_path = StorageLayout::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Settings)?
+            .plan_path(id);
+        plan.save(&plan_path)
+            .map_err(InstallError::io("save the install plan"))?;
+
+        info!(
+            "Planned install of mod {id}: {} roots, {} files would be overwritten, {} conflicts, {} missing dependencies",
+            plan.layout.roots.len(),
+            plan.overwrites.len(),
+            plan.conflicts.len(),
+            plan.missing_dependencies.len()
+        );
+        Ok(plan)
+    }
 
-        // Create a client and start downloading
-        let client = Client::new();
-        let response = client
-            .get(&download_location)
-            .send()
+    /// Works out the order to install mod `id` and the mods it needs that aren't installed in,
+    /// each one after the mods it needs. Nothing is installed, the user confirms the order first.
+    ///
+    /// Every mod is planned (see [`plan_install`](Self::plan_install)) to find out what it
+    /// needs, so installing them afterwards executes those plans. Mods we can't download
+    /// (e.g. SuperBLT) are left to the user. Each plan is claimed in `queue`, so it can't run
+    /// while the mod has another task.
+    ///
+    /// # Returns
+    ///
+    /// The mods to install, in the order to install them in, ending with `id`.
+    pub async fn plan_dependencies(
+        &self,
+        id: u32,
+        window: &tauri::Window,
+        queue: &TaskQueue,
+    ) -> Result<Vec<u32>, InstallError> {
+        let mut needs: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        let mut pending = vec![id];
+        while let Some(next) = pending.pop() {
+            if needs.contains_key(&next) {
+                continue;
+            }
+
+            let task = TaskEmitter::new(window.clone(), next, TaskKind::Plan);
+            let _claim = queue.claim(&task)?;
+            task.started();
+            let plan = self.plan_install(next, &task).await;
+            match &plan {
+                Ok(_) => task.done(),
+                Err(e) => task.failed(e),
+            }
+
+            let dependencies: Vec<u32> = plan?
+                .missing_dependencies
+                .iter()
+                .filter_map(|requirement| requirement.mod_id)
+                .collect();
+            pending.extend(&dependencies);
+            needs.insert(next, dependencies);
+        }
+
+        let order = install_order(id, &needs).map_err(InstallError::DependencyCycle)?;
+        info!(
+            "Mod {id} and its dependencies install in order: {:?}",
+            order
+        );
+        Ok(order)
+    }
+
+    /// Downloads, extracts and registers mod `id`, which must not be installed yet (it's updated
+    /// instead)
+    async fn install_mod(&self, id: u32, task: &TaskEmitter) -> Result<(), InstallError> {
+        ModRegistry::load(GAME_KEY)
+            .map_err(InstallError::Registry)?
+            .ensure_not_installed(id)?;
+
+        let archive = self.download_archive(id, task).await?;
+        self.install_archive(id, archive, task).await
+    }
+
+    /// Extracts and registers the archive of mod `id`, conflicts with other mods are reported
+    /// through `task`
+    async fn install_archive(
+        &self,
+        id: u32,
+        archive: DownloadedArchive,
+        task: &TaskEmitter,
+    ) -> Result<(), InstallError> {
+        let installed = self.copy_archive(id, archive, Some(task)).await?;
+
+        let mut registry = ModRegistry::lock(GAME_KEY)
             .await
-            .map_err(|e| format!("Failed to download file: {}", e))?;
-        // TODO: Emit an error event above ^ (and swap to proper errors, we do this for now to fix an error)
+            .map_err(InstallError::Registry)?;
+        registry.insert(installed);
+        registry.save().map_err(InstallError::Registry)?;
 
-        debug!("Got a response, now reading...");
+        self.deploy_priorities(&mut registry).await;
+        Ok(())
+    }
 
-        let content = response
-            .bytes()
+    /// Extracts the archive of mod `id` and copies it into the game directory, conflicts with
+    /// other mods are reported through `task` (if there is one).
+    ///
+    /// # Returns
+    ///
+    /// The registry entry of the mod, for the caller to record.
+    async fn copy_archive(
+        &self,
+        id: u32,
+        archive: DownloadedArchive,
+        task: Option<&TaskEmitter>,
+    ) -> Result<InstalledMod, InstallError> {
+        let (staged, layout) = self.stage_archive(id, &archive).await?;
+        self.report_conflicts(id, &layout, task)?;
+        self.report_missing_dependencies(id, &staged, &layout, task)?;
+
+        let replaced_dir = StorageLayout::load(GAME_KEY)
             .await
-            .map_err(|e| format!("Failed to read response: {e}"))?;
+            .map_err(InstallError::Settings)?
+            .replaced_dir(id);
+        // The mod isn't installed, anything left in there is from an install that never finished
+        if replaced_dir.exists() {
+            std::fs::remove_dir_all(&replaced_dir)
+                .map_err(InstallError::io("clear the replaced files"))?;
+        }
+        let extracted = install_staged_mod(&staged, layout, id, &replaced_dir).await?;
+
+        let mut installed =
+            InstalledMod::from_extracted(id, archive.name, archive.release, extracted)
+                .ok_or(InstallError::NoModRoot)?;
+        installed.read_metadata();
+        Ok(installed)
+    }
+
+    /// Downloads the latest release of installed mod `id` and replaces the installed files with it
+    async fn update_installed_mod(&self, id: u32, task: &TaskEmitter) -> Result<(), InstallError> {
+        let installed = ModRegistry::load(GAME_KEY)
+            .map_err(InstallError::Registry)?
+            .get(id)
+            .cloned()
+            .ok_or(InstallError::NotInstalled)?;
+
+        if !installed.is_enabled() {
+            return Err(InstallError::Disabled);
+        }
 
-        debug!("Reading done; Writing file...");
-        debug!("Emitting `mod_writing` for {id}");
-        let _ = window.emit("mod_wiring", id);
+        let archive = self.download_archive(id, task).await?;
 
-        let file_path = format!("/tmp/.void/pd2/{}.{}", id, ext);
+        let (staged, layout) = self.stage_archive(id, &archive).await?;
+        self.report_conflicts(id, &layout, Some(task))?;
+        self.report_missing_dependencies(id, &staged, &layout, Some(task))?;
 
-        let _ = fs::create_dir_all("/tmp/.void/pd2")
+        let storage = StorageLayout::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Settings)?;
+        let extracted = replace_installed_mod(&installed, &staged, layout, &storage)
             .await
-            .map_err(|_e| window.emit("mod_error", "MOD.DIR"));
-        // TODO: The same as all the others
+            .map_err(InstallError::Update)?;
 
-        // Create the file
-        let mut file =
-            File::create(&file_path).map_err(|e| format!("Failed to create file: {}", e))?;
+        // The new version is installed whole, what it overrode before is stale
+        let overridden_dir = storage.overridden_dir(id);
+        if overridden_dir.exists() {
+            std::fs::remove_dir_all(&overridden_dir)
+                .map_err(InstallError::io("clear the overridden files"))?;
+        }
 
-        // Write the data
-        let _ = copy(&mut Cursor::new(content), &mut file)
-            .map_err(|e| error!("Failed to write file {e}"));
+        let mut registry = ModRegistry::lock(GAME_KEY)
+            .await
+            .map_err(InstallError::Registry)?;
+        let mut installed =
+            InstalledMod::from_extracted(id, archive.name, archive.release, extracted)
+                .ok_or(InstallError::NoModRoot)?;
+        installed.read_metadata();
+        registry.insert(installed);
+        registry.save().map_err(InstallError::Registry)?;
+
+        self.deploy_priorities(&mut registry).await;
+        Ok(())
+    }
 
-        info!("Successfully wrote file");
-        debug!("Emitting `mod_finishing_up` for {id}");
-        let _ = window.emit("mod_finishing_up", id);
-
-        if ext == "zip" {
-            debug!("Trying to unzip file...");
-            let _path = match PathBuf::from_str(&file_path.to_string()) {
-                Ok(p) => p,
-                Err(e) => {
-                    panic!("Failed to create mod path: {e}");
-                }
-            };
-            unzip_mod(_path, id).await;
-            debug!("Unzipping didn't seem to error.");
-        } else {
-            debug!("Emitting `mod_error` for MOD.UNZIP (.{ext})");
-            let _ = window.emit("mod_error", "MOD.UNZIP");
-            warn!("Unable to unzip file, filetype not supported.")
+    /// Moves the files that lose a conflict out of the game, after a mod was installed or updated.
+    ///
+    /// The mod is installed either way, so failing to do so is only logged.
+    async fn deploy_priorities(&self, registry: &mut ModRegistry) {
+        if let Err(e) = redeploy(GAME_KEY, registry).await {
+            warn!("Failed to deploy the conflict priorities: {e}");
         }
+    }
 
-        debug!("Emitting `mod_done` for {id}");
-        let _ = window.emit("mod_done", id);
+    /// Extracts the archive of mod `id` into its staging directory and plans the install.
+    ///
+    /// If the user confirmed a plan for this archive with `plan_install`, that plan (and what it
+    /// already extracted) is used as is.
+    async fn stage_archive(
+        &self,
+        id: u32,
+        archive: &DownloadedArchive,
+    ) -> Result<(PathBuf, InstallLayout), InstallError> {
+        archive.check()?;
 
-        info!("Mod downloaded successfully!");
+        let storage = StorageLayout::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Settings)?;
+        let staged = storage.staging_dir(id);
+
+        let plan = InstallPlan::load(&storage.plan_path(id))
+            .filter(|plan| plan.sha256 == archive.sha256 && plan.is_staged(&staged));
+        if let Some(plan) = plan {
+            info!("Installing mod {id} as planned");
+            let _ = std::fs::remove_file(storage.plan_path(id));
+            return Ok((staged, plan.layout));
+        }
 
-        return Ok(());
+        debug!("Trying to unzip file...");
+        stage_mod(&archive.path, id).await
+    }
+
+    /// Checks which files installing `layout` as mod `id` would take over from other mods, and
+    /// lets the front end know. Conflicts don't stop the install.
+    fn report_conflicts(
+        &self,
+        id: u32,
+        layout: &InstallLayout,
+        task: Option<&TaskEmitter>,
+    ) -> Result<Vec<FileConflict>, InstallError> {
+        let registry = ModRegistry::load(GAME_KEY).map_err(InstallError::Registry)?;
+        let conflicts = ConflictIndex::build(&registry).check(id, layout);
+
+        if !conflicts.is_empty() {
+            warn!(
+                "Mod {id} writes {} paths that other mods also write",
+                conflicts.len()
+            );
+            if let Some(task) = task {
+                task.conflicts(conflicts.clone());
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Works out which mods the mod staged in `staged` needs that aren't installed, and lets the
+    /// front end know. Missing dependencies don't stop the install, see
+    /// [`plan_dependencies`](Self::plan_dependencies).
+    fn report_missing_dependencies(
+        &self,
+        id: u32,
+        staged: &Path,
+        layout: &InstallLayout,
+        task: Option<&TaskEmitter>,
+    ) -> Result<Vec<Requirement>, InstallError> {
+        let registry = ModRegistry::load(GAME_KEY).map_err(InstallError::Registry)?;
+        let missing = missing_requirements(
+            staged_requirements(id, staged, layout),
+            &registry,
+            &game_dir()?,
+        );
+
+        if !missing.is_empty() {
+            let names: Vec<&str> = missing.iter().map(|r| r.name.as_str()).collect();
+            warn!("Mod {id} needs {}, which isn't installed", names.join(", "));
+            if let Some(task) = task {
+                task.missing_dependencies(missing.clone());
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Downloads the archive of mod `id`, emitting the download events to the front end as it goes.
+    ///
+    /// The archive is taken from the [`ArchiveCache`] if the same upload was downloaded before,
+    /// or if ModWorkshop can't be reached. Fresh downloads are added to the cache.
+    async fn download_archive(
+        &self,
+        id: u32,
+        task: &TaskEmitter,
+    ) -> Result<DownloadedArchive, InstallError> {
+        let mut cache = ArchiveCache::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Cache)?;
+
+        // Get the mod details (name, version) and the download link
+        let Some(details) = self.fetch_mod_details(id).await else {
+            let latest = cache.latest(id).cloned();
+            if let Some(archive) = self.take_from_cache(&mut cache, latest)? {
+                warn!("Couldn't reach ModWorkshop, installing the cached archive of mod {id}");
+                task.installing();
+                return Ok(archive);
+            }
+
+            return Err(InstallError::Download(
+                "Failed to get the mod details from ModWorkshop".to_string(),
+            ));
+        };
+
+        let file_id = details.download.as_ref().and_then(|d| d.id);
+        let cached = file_id.and_then(|file_id| cache.find(id, file_id).cloned());
+        if let Some(archive) = self.take_from_cache(&mut cache, cached)? {
+            info!("Mod {id} is already in the cache, skipping the download");
+            task.installing();
+            return Ok(archive);
+        }
+
+        let download_location = details
+            .download
+            .as_ref()
+            .and_then(|d| d.download_url.clone())
+            .ok_or(InstallError::NoDownload)?;
+
+        debug!("Download URL => {download_location}");
+
+        // Update the front end
+        task.downloading();
+
+        let layout = StorageLayout::load(GAME_KEY)
+            .await
+            .map_err(InstallError::Settings)?;
+        let download_path = layout.download_path(id, file_id.unwrap_or(0));
+
+        // Create a client and start downloading, picking up any partial download we already have
+        let client = Client::new();
+        let downloaded = download_to_file(
+            &client,
+            &download_location,
+            &download_path,
+            task.control(),
+            |progress| task.progress(progress),
+        )
+        .await?;
+        task.writing();
+
+        // Download links don't always end in the file's extension, so look at what we actually got
+        let Some(format) = ArchiveFormat::detect_download(&download_path, &downloaded) else {
+            let _ = std::fs::remove_file(&download_path);
+            let ext = downloaded
+                .file_name
+                .as_deref()
+                .and_then(|name| Path::new(name).extension())
+                .map(|ext| ext.to_string_lossy().to_string())
+                .unwrap_or("?".to_string());
+            warn!(
+                "Unable to unzip {:?} ({:?}), filetype not supported.",
+                downloaded.file_name, downloaded.content_type
+            );
+            return Err(InstallError::UnsupportedArchive(ext));
+        };
+        debug!(
+            "Downloaded {:?} is a {format:?} archive",
+            downloaded.file_name
+        );
+
+        let path = layout.archive_path(id, format.extension());
+        std::fs::rename(&download_path, &path)
+            .map_err(InstallError::io("move the finished download into place"))?;
+
+        let expected = ExpectedFile {
+            size: details.download.as_ref().and_then(|d| d.size),
+            hash: details.download.as_ref().and_then(|d| d.hash.clone()),
+        };
+        let sha256 = match verify_file(&path, &expected) {
+            Ok(sha256) => sha256,
+            Err(e) => {
+                // Don't resume from a bad file next time
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+
+        let cached = cache
+            .add(
+                &path,
+                sha256,
+                id,
+                file_id.unwrap_or(0),
+                details.name,
+                details.release,
+            )
+            .map_err(InstallError::io("move the archive into the cache"))?;
+        cache.save().map_err(InstallError::Cache)?;
+
+        info!("Successfully wrote file");
+        task.installing();
+
+        Ok(DownloadedArchive::from_cache(&cache, cached))
+    }
+
+    /// Checks a cached archive before using it, a damaged archive is dropped from the cache
+    fn take_from_cache(
+        &self,
+        cache: &mut ArchiveCache,
+        cached: Option<CachedArchive>,
+    ) -> Result<Option<DownloadedArchive>, InstallError> {
+        let Some(cached) = cached else {
+            return Ok(None);
+        };
+
+        let intact = cache.verify(&cached);
+        cache.save().map_err(InstallError::Cache)?;
+
+        Ok(intact.then(|| DownloadedArchive::from_cache(cache, cached)))
+    }
+
+    /// Fetches the full details of a single mod from ModWorkshop
+    async fn fetch_mod_details(&self, id: u32) -> Option<ModDownloadAPIResponse> {
+        info!("Getting mod information...");
+        let response = match reqwest::get(format!("https://api.modworkshop.net/mods/{}", id)).await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!("Request failed: {e}");
+                return None;
+            }
+        };
+
+        // Get the text (Body) from the response
+        let text = match response.text().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to read response text: {e}");
+                return None;
+            }
+        };
+
+        match serde_json::from_str::<ModDownloadAPIResponse>(&text) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                error!("Failed to parse JSON: {:#?}", e);
+                None
+            }
+        }
     }
 }
@@ -0,0 +1,411 @@
+use std::{
+    ffi::OsString,
+    fs::{create_dir_all, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use zip::ZipArchive;
+
+use crate::lib::{download::DownloadedFile, error::InstallError, integrity::validate_zip};
+
+/// The kinds of archive mods can be installed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    SevenZip,
+    /// Extracted with whichever of `unrar`, `7z` or `bsdtar` is installed, there is no Rust RAR decoder
+    Rar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Works out the format from a file name or URL, e.g. `mod.zip` or `mod.tar.gz`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            return Some(ArchiveFormat::TarGz);
+        }
+
+        match name.rsplit_once('.')?.1 {
+            "zip" => Some(ArchiveFormat::Zip),
+            "7z" => Some(ArchiveFormat::SevenZip),
+            "rar" => Some(ArchiveFormat::Rar),
+            _ => None,
+        }
+    }
+
+    /// Works out the format from the first bytes of a file
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+        const SIGNATURES: [(&[u8], ArchiveFormat); 6] = [
+            (b"PK\x03\x04", ArchiveFormat::Zip),
+            // An empty zip
+            (b"PK\x05\x06", ArchiveFormat::Zip),
+            // A spanned zip
+            (b"PK\x07\x08", ArchiveFormat::Zip),
+            (b"7z\xBC\xAF\x27\x1C", ArchiveFormat::SevenZip),
+            // Both RAR 4 and RAR 5 start with this
+            (b"Rar!\x1A\x07", ArchiveFormat::Rar),
+            // Mods are never a single gzipped file, so gzip means tar.gz
+            (b"\x1F\x8B", ArchiveFormat::TarGz),
+        ];
+
+        SIGNATURES
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, format)| *format)
+    }
+
+    /// Works out the format from a MIME type, `None` for generic ones like `application/octet-stream`
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/zip" | "application/x-zip" | "application/x-zip-compressed" => {
+                Some(ArchiveFormat::Zip)
+            }
+            "application/x-7z-compressed" => Some(ArchiveFormat::SevenZip),
+            "application/vnd.rar" | "application/x-rar" | "application/x-rar-compressed" => {
+                Some(ArchiveFormat::Rar)
+            }
+            "application/gzip" | "application/x-gzip" | "application/x-gtar"
+            | "application/x-tgz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+
+    /// Works out the format of a file on disk from its contents, falling back to its name
+    pub fn detect(path: &Path) -> Option<Self> {
+        let mut header = [0u8; 8];
+        let read = File::open(path)
+            .and_then(|mut file| read_header(&mut file, &mut header))
+            .unwrap_or(0);
+
+        Self::from_magic(&header[..read]).or_else(|| Self::from_path(path))
+    }
+
+    /// Works out the format of a finished download. The contents are trusted over the file name
+    /// the server gave it (or the one in the URL), which is trusted over its `Content-Type`.
+    pub fn detect_download(path: &Path, download: &DownloadedFile) -> Option<Self> {
+        Self::detect(path)
+            .or_else(|| {
+                download
+                    .file_name
+                    .as_deref()
+                    .and_then(|name| Self::from_path(Path::new(name)))
+            })
+            .or_else(|| {
+                download
+                    .content_type
+                    .as_deref()
+                    .and_then(Self::from_content_type)
+            })
+    }
+
+    /// The extension archives of this format are saved with
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::SevenZip => "7z",
+            ArchiveFormat::Rar => "rar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    /// Checks that the archive is complete before anything is extracted from it.
+    ///
+    /// RAR archives can't be checked without extracting them, the extractor reports damage instead.
+    pub fn validate(self, archive: &Path) -> Result<(), InstallError> {
+        match self {
+            ArchiveFormat::Zip => validate_zip(archive),
+            ArchiveFormat::SevenZip => sevenz_rust::Archive::open(archive)
+                .map(|_| ())
+                .map_err(|e| InstallError::Integrity(format!("The 7z headers can't be read: {e}"))),
+            ArchiveFormat::TarGz => {
+                let file = File::open(archive).map_err(InstallError::io("open the archive"))?;
+                let mut tar = tar::Archive::new(GzDecoder::new(file));
+                let entries = tar.entries().map_err(|e| {
+                    InstallError::Integrity(format!("The tar.gz can't be read: {e}"))
+                })?;
+
+                // Reading every entry to the end makes gzip check its CRC
+                for entry in entries {
+                    let mut entry = entry.map_err(|e| {
+                        InstallError::Integrity(format!("The tar.gz is damaged: {e}"))
+                    })?;
+                    io::copy(&mut entry, &mut io::sink()).map_err(|e| {
+                        InstallError::Integrity(format!("The tar.gz is damaged: {e}"))
+                    })?;
+                }
+                Ok(())
+            }
+            ArchiveFormat::Rar => Ok(()),
+        }
+    }
+
+    /// Extracts everything in `archive` into `destination`.
+    ///
+    /// Entries that would end up outside of `destination` (absolute paths, `..`) are skipped,
+    /// RAR archives with such entries aren't extracted at all.
+    pub fn extract(self, archive: &Path, destination: &Path) -> Result<(), InstallError> {
+        info!(
+            "Extracting {:?} archive \"{}\" to \"{}\"",
+            self,
+            archive.display(),
+            destination.display()
+        );
+        create_dir_all(destination).map_err(InstallError::io("create the staging directory"))?;
+
+        match self {
+            ArchiveFormat::Zip => extract_zip(archive, destination),
+            ArchiveFormat::SevenZip => extract_7z(archive, destination),
+            ArchiveFormat::Rar => extract_rar(archive, destination),
+            ArchiveFormat::TarGz => extract_tar_gz(archive, destination),
+        }
+    }
+}
+
+fn extract_zip(archive: &Path, destination: &Path) -> Result<(), InstallError> {
+    let file = File::open(archive).map_err(InstallError::io("open the zip file"))?;
+    let mut archive = ZipArchive::new(file)?;
+
+    for i in 0..archive.len() {
+        let mut inner_file = archive.by_index(i)?;
+        let outpath = match inner_file.enclosed_name() {
+            Some(path) => destination.join(path),
+            None => continue,
+        };
+
+        if inner_file.is_dir() {
+            create_dir_all(&outpath).map_err(InstallError::io("create a directory"))?;
+        } else {
+            debug!(
+                "File {i} extracted to \"{}\" ({} bytes)",
+                outpath.display(),
+                inner_file.size()
+            );
+            write_entry(&mut inner_file, &outpath)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_7z(archive: &Path, destination: &Path) -> Result<(), InstallError> {
+    let mut reader = sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+        .map_err(|e| InstallError::Extract(e.to_string()))?;
+
+    // The closure can only return a 7z error, so file system errors are kept here
+    let mut failure = None;
+    reader
+        .for_each_entries(|entry, data| {
+            let Some(relative) = enclosed_path(entry.name()) else {
+                warn!("Skipping 7z entry outside of the archive: {}", entry.name());
+                return Ok(true);
+            };
+            let outpath = destination.join(relative);
+
+            let result = if entry.is_directory() {
+                create_dir_all(&outpath).map_err(InstallError::io("create a directory"))
+            } else {
+                write_entry(data, &outpath)
+            };
+
+            match result {
+                Ok(()) => Ok(true),
+                Err(e) => {
+                    failure = Some(e);
+                    Ok(false)
+                }
+            }
+        })
+        .map_err(|e| InstallError::Extract(e.to_string()))?;
+
+    failure.map_or(Ok(()), Err)
+}
+
+fn extract_tar_gz(archive: &Path, destination: &Path) -> Result<(), InstallError> {
+    let file = File::open(archive).map_err(InstallError::io("open the archive"))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+
+    let entries = tar
+        .entries()
+        .map_err(|e| InstallError::Extract(e.to_string()))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| InstallError::Extract(e.to_string()))?;
+        // `unpack_in` refuses to write outside of `destination`
+        entry
+            .unpack_in(destination)
+            .map_err(|e| InstallError::Extract(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// A program we can extract RAR archives with
+struct RarExtractor {
+    program: &'static str,
+    /// Lists the entries of the archive, see [`rar_entries`]
+    list: Vec<OsString>,
+    extract: Vec<OsString>,
+}
+
+/// The programs we try to extract RAR archives with, in order
+fn rar_extractors(archive: &Path, destination: &Path) -> Vec<RarExtractor> {
+    let mut unrar_destination = destination.as_os_str().to_owned();
+    unrar_destination.push(std::path::MAIN_SEPARATOR_STR);
+    let mut sevenzip_destination = OsString::from("-o");
+    sevenzip_destination.push(destination);
+
+    let bsdtar = |program| RarExtractor {
+        program,
+        list: vec!["-tf".into(), archive.into()],
+        extract: vec![
+            "-xf".into(),
+            archive.into(),
+            "-C".into(),
+            destination.into(),
+        ],
+    };
+
+    vec![
+        RarExtractor {
+            program: "unrar",
+            list: vec!["lb".into(), archive.into()],
+            extract: vec!["x".into(), "-y".into(), archive.into(), unrar_destination],
+        },
+        RarExtractor {
+            program: "7z",
+            list: vec!["l".into(), "-slt".into(), archive.into()],
+            extract: vec![
+                "x".into(),
+                "-y".into(),
+                sevenzip_destination,
+                archive.into(),
+            ],
+        },
+        bsdtar("bsdtar"),
+        // On Windows `tar` is bsdtar, which can read RAR
+        bsdtar("tar"),
+    ]
+}
+
+/// The entry names in what `program` printed when listing an archive: one name per line, except
+/// for `7z -slt` which prints a `Path = ` line for each entry after the archive's own details
+pub fn rar_entries(program: &str, listing: &str) -> Vec<String> {
+    let lines = listing.lines().map(|line| line.trim_end_matches('\r'));
+    if program != "7z" {
+        return lines
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+
+    lines
+        .skip_while(|line| !line.starts_with("----------"))
+        .filter_map(|line| line.strip_prefix("Path = "))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether the entry `name` would be written outside of the directory it's extracted to
+pub fn escapes(name: &str) -> bool {
+    Path::new(&name.replace('\\', "/"))
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Runs `program`, `Ok(None)` if it isn't installed
+fn run(program: &str, args: &[OsString]) -> Result<Option<String>, String> {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
+        }
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn extract_rar(archive: &Path, destination: &Path) -> Result<(), InstallError> {
+    let mut last_error = None;
+
+    for extractor in rar_extractors(archive, destination) {
+        let program = extractor.program;
+
+        // Unlike our own extractors these write whatever path is in the archive, so check first
+        let listing = match run(program, &extractor.list) {
+            Ok(Some(listing)) => listing,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("{program} failed to list the RAR: {e}");
+                last_error.get_or_insert(format!("{program}: {e}"));
+                continue;
+            }
+        };
+        if let Some(entry) = rar_entries(program, &listing)
+            .into_iter()
+            .find(|entry| escapes(entry))
+        {
+            return Err(InstallError::Extract(format!(
+                "The archive has an entry outside of it: {entry}"
+            )));
+        }
+
+        match run(program, &extractor.extract) {
+            Ok(Some(_)) => {
+                debug!("Extracted RAR with {program}");
+                return Ok(());
+            }
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("{program} failed to extract the RAR: {e}");
+                last_error.get_or_insert(format!("{program}: {e}"));
+            }
+        }
+    }
+
+    Err(InstallError::Extract(last_error.unwrap_or(
+        "RAR archives need unrar, 7-Zip or bsdtar to be installed".to_string(),
+    )))
+}
+
+/// Turns the path of an archive entry into a relative path, `None` if it would escape the
+/// directory it's extracted to
+pub fn enclosed_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in Path::new(&name.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Reads up to `header.len()` bytes, fewer only if the file is shorter
+fn read_header(file: &mut File, header: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+fn write_entry(data: &mut dyn Read, outpath: &Path) -> Result<(), InstallError> {
+    if let Some(parent) = outpath.parent() {
+        create_dir_all(parent).map_err(InstallError::io("create a parent directory"))?;
+    }
+
+    let mut outfile = File::create(outpath).map_err(InstallError::io("create a file"))?;
+    io::copy(data, &mut outfile).map_err(InstallError::io("extract a file"))?;
+    Ok(())
+}
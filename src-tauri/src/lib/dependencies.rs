@@ -0,0 +1,274 @@
+use std::{collections::BTreeMap, path::Path};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lib::{layout::InstallLayout, main_xml::MainXml, mod_txt::ModTxt},
+    registry::{InstallTarget, ModRegistry},
+};
+
+/// The ModWorkshop id of BeardLib, which every `main.xml` mod needs
+pub const BEARDLIB_ID: u32 = 14924;
+
+/// A mod other mods depend on by name, and how to tell it's installed
+struct KnownMod {
+    name: &'static str,
+    /// Its ModWorkshop id, `None` if it isn't on ModWorkshop
+    mod_id: Option<u32>,
+    /// Where it's installed in the game directory, any one of these means it is
+    installed_as: &'static [(InstallTarget, &'static str)],
+}
+
+const KNOWN_MODS: [KnownMod; 2] = [
+    KnownMod {
+        name: "BeardLib",
+        mod_id: Some(BEARDLIB_ID),
+        installed_as: &[(InstallTarget::Mods, "BeardLib")],
+    },
+    // SuperBLT is installed from its own site, not ModWorkshop
+    KnownMod {
+        name: "SuperBLT",
+        mod_id: None,
+        installed_as: &[
+            (InstallTarget::Game, "IPHLPAPI.dll"),
+            (InstallTarget::Game, "WSOCK32.dll"),
+        ],
+    },
+];
+
+impl KnownMod {
+    fn find(name: &str, mod_id: Option<u32>) -> Option<&'static KnownMod> {
+        let name = normalize(name);
+        KNOWN_MODS.iter().find(|known| {
+            normalize(known.name) == name || (mod_id.is_some() && known.mod_id == mod_id)
+        })
+    }
+}
+
+/// A mod that a mod needs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Requirement {
+    pub name: String,
+    /// Its ModWorkshop id, `None` if we don't know where to download it from
+    pub mod_id: Option<u32>,
+}
+
+impl Requirement {
+    fn named(name: &str, mod_id: Option<u32>) -> Self {
+        let known = KnownMod::find(name, mod_id);
+        Requirement {
+            name: known.map_or(name, |k| k.name).to_string(),
+            mod_id: mod_id.or(known.and_then(|k| k.mod_id)),
+        }
+    }
+
+    fn is_same(&self, other: &Requirement) -> bool {
+        match (self.mod_id, other.mod_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => normalize(&self.name) == normalize(&other.name),
+        }
+    }
+}
+
+/// What a mod needs, from its `mod.txt` or `main.xml`.
+///
+/// Declared dependencies are resolved to ModWorkshop ids where we can. On top of those, BLT mods
+/// need SuperBLT to be loaded at all, and `main.xml` mods need BeardLib.
+pub fn requirements(mod_txt: Option<&ModTxt>, main_xml: Option<&MainXml>) -> Vec<Requirement> {
+    let mut found = Vec::new();
+
+    if let Some(mod_txt) = mod_txt {
+        found.push(Requirement::named("SuperBLT", None));
+        found.extend(mod_txt.dependencies.iter().map(|dependency| {
+            let mod_id = [&dependency.download_url, &dependency.meta_url]
+                .into_iter()
+                .flatten()
+                .find_map(|url| modworkshop_id(url));
+            Requirement::named(
+                dependency
+                    .display_name
+                    .as_deref()
+                    .unwrap_or(&dependency.identifier),
+                mod_id,
+            )
+        }));
+    }
+
+    if let Some(main_xml) = main_xml {
+        found.push(Requirement::named("BeardLib", None));
+        found.extend(main_xml.dependencies.iter().map(|dependency| {
+            let on_modworkshop = dependency
+                .provider
+                .as_deref()
+                .is_none_or(|p| p.eq_ignore_ascii_case("modworkshop"));
+            let mod_id = dependency
+                .id
+                .as_deref()
+                .filter(|_| on_modworkshop)
+                .and_then(|id| id.trim().parse().ok());
+            Requirement::named(&dependency.name, mod_id)
+        }));
+    }
+
+    let mut requirements: Vec<Requirement> = Vec::new();
+    for requirement in found {
+        if !requirements.iter().any(|r| r.is_same(&requirement)) {
+            requirements.push(requirement);
+        }
+    }
+    requirements
+}
+
+/// What the mod staged in `staged` needs but doesn't bring along, read from the `mod.txt` and
+/// `main.xml` of its roots.
+///
+/// # Arguments
+///
+/// * `mod_id` - The ID of the mod, it doesn't need itself.
+/// * `staged` - The staging directory the archive was extracted to.
+/// * `layout` - Where everything in `staged` goes.
+pub fn staged_requirements(mod_id: u32, staged: &Path, layout: &InstallLayout) -> Vec<Requirement> {
+    let mut needed: Vec<Requirement> = Vec::new();
+    for root in &layout.roots {
+        let dir = staged.join(&root.source);
+        let declared = match root.target {
+            InstallTarget::Mods => requirements(ModTxt::load(&dir.join("mod.txt")).as_ref(), None),
+            InstallTarget::ModOverrides => {
+                requirements(None, MainXml::load(&dir.join("main.xml")).as_ref())
+            }
+            InstallTarget::Game => Vec::new(),
+        };
+        for requirement in declared {
+            if !needed.iter().any(|r| r.is_same(&requirement)) {
+                needed.push(requirement);
+            }
+        }
+    }
+
+    // Archives that bundle BeardLib or SuperBLT bring their own
+    let provides = |requirement: &Requirement| {
+        KnownMod::find(&requirement.name, requirement.mod_id).is_some_and(|known| {
+            known.installed_as.iter().any(|(target, name)| {
+                layout.roots.iter().any(|root| {
+                    root.target == *target
+                        && root
+                            .install_dir
+                            .file_name()
+                            .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+                })
+            })
+        })
+    };
+
+    needed
+        .into_iter()
+        .filter(|requirement| requirement.mod_id != Some(mod_id) && !provides(requirement))
+        .collect()
+}
+
+/// The requirements that aren't satisfied: not in the registry, and (for the mods we know)
+/// not installed some other way, e.g. by hand.
+pub fn missing_requirements(
+    requirements: Vec<Requirement>,
+    registry: &ModRegistry,
+    game_dir: &Path,
+) -> Vec<Requirement> {
+    requirements
+        .into_iter()
+        .filter(|requirement| {
+            let registered = requirement
+                .mod_id
+                .is_some_and(|id| registry.get(id).is_some());
+            let in_game =
+                KnownMod::find(&requirement.name, requirement.mod_id).is_some_and(|known| {
+                    known
+                        .installed_as
+                        .iter()
+                        .any(|(target, name)| target.dir(game_dir).join(name).exists())
+                });
+
+            if registered || in_game {
+                debug!("{} is installed", requirement.name);
+            }
+            !registered && !in_game
+        })
+        .collect()
+}
+
+/// Works out the order to install `root` and the mods it needs in, every mod after the ones it
+/// needs.
+///
+/// `needs` maps each mod to the mods it needs that aren't installed. Mods that aren't in it
+/// don't need anything.
+///
+/// # Errors
+///
+/// The mods in a cycle, starting and ending with the same mod, if mods need each other.
+pub fn install_order(root: u32, needs: &BTreeMap<u32, Vec<u32>>) -> Result<Vec<u32>, Vec<u32>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Visiting,
+        Done,
+    }
+
+    let mut marks: BTreeMap<u32, Mark> = BTreeMap::new();
+    let mut order = Vec::new();
+    // Depth first, `path` is the chain of mods that led to the one on top of the stack
+    let mut path: Vec<u32> = Vec::new();
+    let mut stack: Vec<(u32, usize)> = vec![(root, 0)];
+
+    while let Some((id, next)) = stack.pop() {
+        if next == 0 {
+            match marks.get(&id) {
+                Some(Mark::Done) => continue,
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|p| *p == id).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(id);
+                    warn!("Dependency cycle: {:?}", cycle);
+                    return Err(cycle);
+                }
+                None => {
+                    marks.insert(id, Mark::Visiting);
+                    path.push(id);
+                }
+            }
+        }
+
+        let dependencies = needs.get(&id).map(Vec::as_slice).unwrap_or_default();
+        match dependencies.get(next) {
+            Some(dependency) => {
+                stack.push((id, next + 1));
+                stack.push((*dependency, 0));
+            }
+            None => {
+                marks.insert(id, Mark::Done);
+                path.pop();
+                order.push(id);
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// The ModWorkshop id in a mod page or download URL, e.g. `https://modworkshop.net/mod/14924`
+pub fn modworkshop_id(url: &str) -> Option<u32> {
+    let (_, path) = url.split_once("modworkshop.net/")?;
+    let mut segments = path.split(['/', '?', '#']);
+    while let Some(segment) = segments.next() {
+        if segment == "mod" {
+            return segments.next()?.parse().ok();
+        }
+    }
+    None
+}
+
+/// Lower case without spaces, dashes or underscores, so `Beard Lib` and `beardlib` match
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
@@ -0,0 +1,414 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use reqwest::{
+    header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+    StatusCode, Url,
+};
+use serde::Serialize;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::lib::{error::InstallError, events::TaskControl};
+
+/// How often progress is reported while downloading
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many times we try a download before giving up
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// How long to wait before retrying a failed download, multiplied by the attempt number
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How far along a download is
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DownloadProgress {
+    pub received: u64,
+    /// The size of the download (from `Content-Length`), if the server told us
+    pub total: Option<u64>,
+    /// The average speed since the download started
+    pub bytes_per_second: u64,
+    /// The estimated time left, only known if `total` is
+    pub eta_seconds: Option<u64>,
+}
+
+impl DownloadProgress {
+    /// Works out the speed and ETA of a download that has received `received` bytes in `elapsed`
+    pub fn new(received: u64, total: Option<u64>, elapsed: Duration) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        let bytes_per_second = if seconds > 0.0 {
+            (received as f64 / seconds) as u64
+        } else {
+            0
+        };
+
+        let eta_seconds = match total {
+            Some(total) if bytes_per_second > 0 => {
+                Some(total.saturating_sub(received).div_ceil(bytes_per_second))
+            }
+            _ => None,
+        };
+
+        DownloadProgress {
+            received,
+            total,
+            bytes_per_second,
+            eta_seconds,
+        }
+    }
+}
+
+/// Keeps count of a download, and decides when it's time to report progress again
+pub struct ProgressTracker {
+    started: Instant,
+    last_report: Instant,
+    /// How much was already downloaded before this attempt (when resuming)
+    offset: u64,
+    received: u64,
+    total: Option<u64>,
+}
+
+impl ProgressTracker {
+    /// Tracks a download that picks up after `offset` bytes (0 for a fresh download),
+    /// the speed only counts new bytes
+    pub fn resuming(offset: u64, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        ProgressTracker {
+            started: now,
+            last_report: now,
+            offset,
+            received: offset,
+            total,
+        }
+    }
+
+    /// Counts `bytes` more as received, returning the progress if [`PROGRESS_INTERVAL`] has
+    /// passed since the last report
+    pub fn advance(&mut self, bytes: u64) -> Option<DownloadProgress> {
+        self.received += bytes;
+
+        if self.last_report.elapsed() < PROGRESS_INTERVAL {
+            return None;
+        }
+
+        self.last_report = Instant::now();
+        Some(self.progress())
+    }
+
+    pub fn progress(&self) -> DownloadProgress {
+        let mut progress = DownloadProgress::new(
+            self.received - self.offset,
+            self.total.map(|total| total.saturating_sub(self.offset)),
+            self.started.elapsed(),
+        );
+        progress.received = self.received;
+        progress.total = self.total;
+        progress
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+/// A finished download, and what the server told us about the file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DownloadedFile {
+    pub size: u64,
+    /// The file name from `Content-Disposition`, falling back to the last segment of the URL
+    pub file_name: Option<String>,
+    /// The MIME type from `Content-Type`, without parameters
+    pub content_type: Option<String>,
+}
+
+impl DownloadedFile {
+    fn from_headers(size: u64, headers: &HeaderMap, url: &str) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+        DownloadedFile {
+            size,
+            file_name: header(CONTENT_DISPOSITION)
+                .and_then(content_disposition_file_name)
+                .or_else(|| url_file_name(url)),
+            content_type: header(CONTENT_TYPE)
+                .and_then(|v| v.split(';').next())
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// The path a download is written to until it's complete, `{destination}.part`
+pub fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    destination.with_file_name(name)
+}
+
+/// Downloads `url` into `destination`, calling `on_progress` every [`PROGRESS_INTERVAL`] and once
+/// more when it's done.
+///
+/// The body is written to [`partial_path`] chunk by chunk and only renamed to `destination`
+/// once all of it has been written, so memory use stays flat and `destination` is never half written.
+///
+/// If the connection drops, the download is picked back up with a `Range` request (up to
+/// [`MAX_ATTEMPTS`] times). A `.part` file left over from an earlier run is resumed the same way.
+/// Servers that don't support ranges just send the whole file again, in which case we start over.
+/// A range that doesn't start where the `.part` file ends is thrown away along with it.
+///
+/// Getting more bytes than `Content-Length` promised, or writing fewer than were received, is an
+/// [`InstallError::Integrity`] error and isn't retried.
+///
+/// The download stops with [`InstallError::Paused`] or [`InstallError::Cancelled`] when `control`
+/// says so. A paused download keeps its `.part` file, a cancelled one doesn't.
+///
+/// # Returns
+///
+/// The size of the downloaded file, and the name and type the server gave it.
+pub async fn download_to_file(
+    client: &reqwest::Client,
+    url: &str,
+    destination: &Path,
+    control: &TaskControl,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<DownloadedFile, InstallError> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(InstallError::io("create the download directory"))?;
+    }
+
+    let part_path = partial_path(destination);
+    let mut attempt = 1;
+
+    let downloaded = loop {
+        match download_attempt(client, url, &part_path, control, &mut on_progress).await {
+            Ok(downloaded) => break downloaded,
+            Err(InstallError::Cancelled) => {
+                let _ = fs::remove_file(&part_path).await;
+                return Err(InstallError::Cancelled);
+            }
+            // Only network errors are worth another go, a full disk will still be full
+            Err(InstallError::Download(e)) if attempt < MAX_ATTEMPTS => {
+                warn!("Download attempt {attempt} failed, retrying: {e}");
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY * attempt) => {}
+                    stop = control.stopped() => return Err(stop),
+                }
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    fs::rename(&part_path, destination)
+        .await
+        .map_err(InstallError::io("move the finished download into place"))?;
+
+    Ok(downloaded)
+}
+
+/// Makes a single request for `url`, resuming `part_path` if it has anything in it
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    part_path: &Path,
+    control: &TaskControl,
+    on_progress: &mut impl FnMut(DownloadProgress),
+) -> Result<DownloadedFile, InstallError> {
+    control.check()?;
+    let existing = fs::metadata(part_path).await.map(|m| m.len()).unwrap_or(0);
+
+    let mut request = client.get(url);
+    if existing > 0 {
+        info!("Trying to resume download from {existing} bytes");
+        request = request.header(RANGE, format!("bytes={existing}-"));
+    }
+
+    let response = tokio::select! {
+        response = request.send() => response.map_err(|e| InstallError::Download(e.to_string()))?,
+        stop = control.stopped() => return Err(stop),
+    };
+
+    if existing > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // Whatever we have doesn't line up with the file anymore, start from scratch next attempt
+        let _ = fs::remove_file(part_path).await;
+        return Err(InstallError::Download(
+            "The server rejected the resume request".to_string(),
+        ));
+    }
+
+    let mut response = response
+        .error_for_status()
+        .map_err(|e| InstallError::Download(e.to_string()))?;
+
+    let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+    if partial && content_range_start(&response) != Some(existing) {
+        // Appending it would stitch two different parts together, start from scratch next attempt
+        let _ = fs::remove_file(part_path).await;
+        return Err(InstallError::Download(format!(
+            "The server sent a range that doesn't start at {existing} bytes"
+        )));
+    }
+    let resumed = existing > 0 && partial;
+
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(part_path).await
+    } else {
+        if existing > 0 {
+            info!("Server doesn't support resuming, starting over");
+        }
+        File::create(part_path).await
+    }
+    .map_err(InstallError::io("open the archive file"))?;
+
+    let offset = if resumed { existing } else { 0 };
+    let total = response.content_length().map(|length| length + offset);
+    let mut tracker = ProgressTracker::resuming(offset, total);
+    let headers = response.headers().clone();
+
+    loop {
+        let next = tokio::select! {
+            next = response.chunk() => next.map_err(|e| InstallError::Download(e.to_string())),
+            stop = control.stopped() => Err(stop),
+        };
+
+        let chunk = match next {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                // Keep what we have so the next attempt (or a resume) can carry on from here
+                let _ = file.flush().await;
+                return Err(e);
+            }
+        };
+
+        file.write_all(&chunk)
+            .await
+            .map_err(InstallError::io("write the archive file"))?;
+
+        if let Some(progress) = tracker.advance(chunk.len() as u64) {
+            on_progress(progress);
+        }
+    }
+
+    file.flush()
+        .await
+        .map_err(InstallError::io("write the archive file"))?;
+
+    on_progress(tracker.progress());
+
+    let written = fs::metadata(part_path)
+        .await
+        .map_err(InstallError::io("read the archive file"))?
+        .len();
+    if written != tracker.received() {
+        let _ = fs::remove_file(part_path).await;
+        return Err(InstallError::Integrity(format!(
+            "Received {} bytes, but {written} bytes were written",
+            tracker.received()
+        )));
+    }
+
+    if let Some(total) = total {
+        if tracker.received() < total {
+            return Err(InstallError::Download(format!(
+                "Connection closed after {} of {total} bytes",
+                tracker.received()
+            )));
+        }
+        if tracker.received() > total {
+            let _ = fs::remove_file(part_path).await;
+            return Err(InstallError::Integrity(format!(
+                "Received {} bytes, but Content-Length was {total}",
+                tracker.received()
+            )));
+        }
+    }
+
+    Ok(DownloadedFile::from_headers(
+        tracker.received(),
+        &headers,
+        url,
+    ))
+}
+
+/// Gets where the body of a `206 Partial Content` response starts, from `Content-Range: bytes {start}-{end}/{size}`
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Gets the file name out of a `Content-Disposition` header, preferring the RFC 5987
+/// `filename*=UTF-8''name` form over the plain `filename="name"`
+pub fn content_disposition_file_name(value: &str) -> Option<String> {
+    let mut plain = None;
+
+    for parameter in value.split(';') {
+        let Some((key, name)) = parameter.split_once('=') else {
+            continue;
+        };
+        let name = name.trim().trim_matches('"');
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                let encoded = name.split_once("''").map_or(name, |(_, encoded)| encoded);
+                let decoded = percent_decode(encoded);
+                if !decoded.is_empty() {
+                    return Some(decoded);
+                }
+            }
+            "filename" if !name.is_empty() => plain = Some(name.to_string()),
+            _ => {}
+        }
+    }
+
+    plain
+}
+
+/// The last segment of the path of `url`, ignoring the query string and fragment
+pub fn url_file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    (!name.is_empty()).then(|| percent_decode(name))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,187 @@
+use std::{fmt, io, path::PathBuf};
+
+use serde::Serialize;
+use zip::result::ZipError;
+
+/// Everything that can go wrong while downloading, installing or managing a mod
+#[derive(Debug)]
+pub enum InstallError {
+    /// ModWorkshop didn't give us anything to download
+    NoDownload,
+    /// The request for the archive failed
+    Download(String),
+    /// The archive type isn't one we can extract (the extension)
+    UnsupportedArchive(String),
+    /// The archive is corrupt or not actually an archive
+    InvalidArchive(ZipError),
+    /// Extracting a (non-zip) archive failed
+    Extract(String),
+    /// The download doesn't match its expected size or hash, or the archive is truncated
+    Integrity(String),
+    /// The archive didn't contain a `mod.txt` or `main.xml`, and isn't laid out like the game directory
+    NoModRoot,
+    /// Two mods in the archive would be installed to the same directory
+    DuplicateRoot(PathBuf),
+    SteamNotFound,
+    /// The game (Steam app id) isn't installed
+    GameNotInstalled(u32),
+    Settings(String),
+    Registry(String),
+    /// Reading or updating the archive cache failed
+    Cache(String),
+    NotInstalled,
+    /// Installing a mod that is already installed (or disabled), it has to be updated instead
+    AlreadyInstalled,
+    Disabled,
+    /// Updating failed, the previous version was restored (if it could be)
+    Update(String),
+    /// The mods need each other, starting and ending with the same mod
+    DependencyCycle(Vec<u32>),
+    /// The task was waiting for the install of mod `0`, which didn't finish
+    DependencyFailed(u32),
+    /// The task was paused by the user
+    Paused,
+    /// The task was cancelled by the user
+    Cancelled,
+    /// Mod `0` already has a task that isn't finished, two tasks would fight over its files
+    AlreadyQueued(u32),
+    /// The task panicked, `0` is what it panicked with
+    Panicked(String),
+    /// A file system operation failed, `action` describes what we were doing
+    Io {
+        action: String,
+        source: io::Error,
+    },
+}
+
+/// The error details of a failed [`ModTaskEvent`](crate::lib::events::ModTaskEvent)
+#[derive(Serialize, Clone, Debug)]
+pub struct ModErrorPayload {
+    pub code: &'static str,
+    pub message: String,
+    pub mod_id: u32,
+}
+
+impl InstallError {
+    /// Wraps an `io::Error` with a description of what we were trying to do
+    pub fn io(action: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let action = action.into();
+        move |source| InstallError::Io { action, source }
+    }
+
+    /// A short, stable code the front end can match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            InstallError::NoDownload => "MOD.NO_DOWNLOAD",
+            InstallError::Download(_) => "MOD.DOWNLOAD",
+            InstallError::UnsupportedArchive(_) => "MOD.UNZIP",
+            InstallError::InvalidArchive(_) => "MOD.INVALID_ARCHIVE",
+            InstallError::Extract(_) => "MOD.EXTRACT",
+            InstallError::Integrity(_) => "MOD.INTEGRITY",
+            InstallError::NoModRoot => "MOD.NO_ROOT",
+            InstallError::DuplicateRoot(_) => "MOD.DUPLICATE_ROOT",
+            InstallError::SteamNotFound => "STEAM.NOT_FOUND",
+            InstallError::GameNotInstalled(_) => "GAME.NOT_INSTALLED",
+            InstallError::Settings(_) => "SETTINGS",
+            InstallError::Registry(_) => "MOD.REGISTRY",
+            InstallError::Cache(_) => "MOD.CACHE",
+            InstallError::NotInstalled => "MOD.NOT_INSTALLED",
+            InstallError::AlreadyInstalled => "MOD.ALREADY_INSTALLED",
+            InstallError::Disabled => "MOD.DISABLED",
+            InstallError::Update(_) => "MOD.UPDATE",
+            InstallError::DependencyCycle(_) => "MOD.DEPENDENCY_CYCLE",
+            InstallError::DependencyFailed(_) => "MOD.DEPENDENCY_FAILED",
+            InstallError::Paused => "TASK.PAUSED",
+            InstallError::Cancelled => "TASK.CANCELLED",
+            InstallError::AlreadyQueued(_) => "TASK.ALREADY_QUEUED",
+            InstallError::Panicked(_) => "TASK.PANICKED",
+            InstallError::Io { source, .. } if source.kind() == io::ErrorKind::StorageFull => {
+                "MOD.DISK_FULL"
+            }
+            InstallError::Io { .. } => "MOD.IO",
+        }
+    }
+
+    pub fn payload(&self, mod_id: u32) -> ModErrorPayload {
+        ModErrorPayload {
+            code: self.code(),
+            message: self.to_string(),
+            mod_id,
+        }
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::NoDownload => write!(f, "This mod has no file to download"),
+            InstallError::Download(e) => write!(f, "Failed to download the mod: {e}"),
+            InstallError::UnsupportedArchive(ext) => {
+                write!(
+                    f,
+                    "Unable to extract .{ext} files, the file type isn't supported"
+                )
+            }
+            InstallError::InvalidArchive(e) => write!(f, "The archive is invalid: {e}"),
+            InstallError::Extract(e) => write!(f, "Failed to extract the archive: {e}"),
+            InstallError::Integrity(e) => {
+                write!(f, "The download is damaged or incomplete: {e}")
+            }
+            InstallError::NoModRoot => write!(
+                f,
+                "Couldn't find a mod.txt, main.xml or mods directory in the archive, don't know where to install it"
+            ),
+            InstallError::DuplicateRoot(path) => write!(
+                f,
+                "More than one mod in the archive would be installed to \"{}\"",
+                path.display()
+            ),
+            InstallError::SteamNotFound => write!(f, "Couldn't find your Steam install"),
+            InstallError::GameNotInstalled(app_id) => {
+                write!(f, "The game ({app_id}) isn't installed through Steam")
+            }
+            InstallError::Settings(e) => write!(f, "Failed to load settings: {e}"),
+            InstallError::Registry(e) => write!(f, "Failed to update the installed mods: {e}"),
+            InstallError::Cache(e) => write!(f, "Failed to update the archive cache: {e}"),
+            InstallError::NotInstalled => write!(f, "This mod isn't installed"),
+            InstallError::AlreadyInstalled => {
+                write!(f, "This mod is already installed, update it instead")
+            }
+            InstallError::Disabled => write!(f, "This mod is disabled, enable it first"),
+            InstallError::Update(e) => write!(f, "Failed to update the mod: {e}"),
+            InstallError::DependencyCycle(cycle) => {
+                let cycle: Vec<String> = cycle.iter().map(u32::to_string).collect();
+                write!(f, "These mods need each other: {}", cycle.join(" -> "))
+            }
+            InstallError::DependencyFailed(id) => {
+                write!(f, "Mod {id}, which this mod needs, wasn't installed")
+            }
+            InstallError::Paused => write!(f, "The download was paused"),
+            InstallError::Cancelled => write!(f, "The download was cancelled"),
+            InstallError::AlreadyQueued(id) => {
+                write!(f, "Mod {id} already has a task in the queue")
+            }
+            InstallError::Panicked(e) => write!(f, "The task crashed: {e}"),
+            InstallError::Io { source, .. } if source.kind() == io::ErrorKind::StorageFull => {
+                write!(f, "Your disk is full")
+            }
+            InstallError::Io { action, source } => write!(f, "Failed to {action}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for InstallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InstallError::InvalidArchive(e) => Some(e),
+            InstallError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ZipError> for InstallError {
+    fn from(e: ZipError) -> Self {
+        InstallError::InvalidArchive(e)
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::debug;
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{
+    conflicts::FileConflict,
+    lib::{
+        dependencies::Requirement,
+        download::DownloadProgress,
+        error::{InstallError, ModErrorPayload},
+    },
+};
+
+/// The single event channel all mod task events are sent on
+pub const MOD_TASK_EVENT: &str = "mod_task";
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a task is doing to a mod
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskKind {
+    Install,
+    Update,
+    Uninstall,
+    /// Downloading and inspecting an archive to show what installing it would do
+    Plan,
+}
+
+/// Identifies a task, included in every event so the front end can tell concurrent tasks apart
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskRef {
+    pub task_id: u64,
+    pub mod_id: u32,
+    pub kind: TaskKind,
+}
+
+/// What the queue wants a running task to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskSignal {
+    Run,
+    /// Stop downloading but keep the partial download so it can be resumed
+    Pause,
+    /// Stop downloading and throw away the partial download
+    Cancel,
+}
+
+/// The receiving end of the [`TaskSignal`]s sent to a task.
+///
+/// Only the download checks for signals, once a mod is being installed it runs to completion
+/// so the game directory is never left half written.
+#[derive(Clone, Debug, Default)]
+pub struct TaskControl {
+    receiver: Option<watch::Receiver<TaskSignal>>,
+}
+
+impl TaskControl {
+    /// Creates a control along with the sender used to signal it
+    pub fn channel() -> (watch::Sender<TaskSignal>, Self) {
+        let (sender, receiver) = watch::channel(TaskSignal::Run);
+        (
+            sender,
+            TaskControl {
+                receiver: Some(receiver),
+            },
+        )
+    }
+
+    /// Returns the matching error if the task has been told to stop
+    pub fn check(&self) -> Result<(), InstallError> {
+        match self.receiver.as_ref().map(|r| *r.borrow()) {
+            Some(TaskSignal::Pause) => Err(InstallError::Paused),
+            Some(TaskSignal::Cancel) => Err(InstallError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until the task is told to stop, returning the matching error.
+    /// Never finishes for tasks that aren't controlled by the queue.
+    pub async fn stopped(&self) -> InstallError {
+        if let Some(mut receiver) = self.receiver.clone() {
+            if let Ok(signal) = receiver.wait_for(|s| *s != TaskSignal::Run).await {
+                return match *signal {
+                    TaskSignal::Pause => InstallError::Paused,
+                    _ => InstallError::Cancelled,
+                };
+            }
+        }
+
+        std::future::pending().await
+    }
+}
+
+/// The lifecycle of a download/install/uninstall task, sent on [`MOD_TASK_EVENT`].
+///
+/// Serialized as a flat object tagged by `phase`, e.g.
+/// `{ "phase": "downloading", "task_id": 3, "mod_id": 12345, "kind": "install" }`
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum ModTaskEvent {
+    /// Waiting for a free download slot
+    Queued(TaskRef),
+    Started(TaskRef),
+    Downloading(TaskRef),
+    /// Sent periodically while the archive is downloading
+    Progress {
+        #[serde(flatten)]
+        task: TaskRef,
+        #[serde(flatten)]
+        progress: DownloadProgress,
+    },
+    Writing(TaskRef),
+    /// The mod writes paths that other installed mods write too, sent before it's installed
+    Conflicts {
+        #[serde(flatten)]
+        task: TaskRef,
+        conflicts: Vec<FileConflict>,
+    },
+    /// The mod needs mods that aren't installed, sent before it's installed
+    MissingDependencies {
+        #[serde(flatten)]
+        task: TaskRef,
+        dependencies: Vec<Requirement>,
+    },
+    Installing(TaskRef),
+    Removing {
+        #[serde(flatten)]
+        task: TaskRef,
+        removed: usize,
+        total: usize,
+    },
+    Done(TaskRef),
+    Paused(TaskRef),
+    Cancelled(TaskRef),
+    Failed {
+        #[serde(flatten)]
+        task: TaskRef,
+        error: ModErrorPayload,
+    },
+}
+
+/// Sends the events of a single task to the front end
+#[derive(Clone)]
+pub struct TaskEmitter {
+    /// `None` for tasks nobody is watching, their events are only logged
+    window: Option<tauri::Window>,
+    task: TaskRef,
+    control: TaskControl,
+}
+
+impl TaskEmitter {
+    /// Starts a new task with a fresh task id
+    pub fn new(window: tauri::Window, mod_id: u32, kind: TaskKind) -> Self {
+        TaskEmitter {
+            window: Some(window),
+            ..Self::detached(mod_id, kind)
+        }
+    }
+
+    /// Starts a new task with a fresh task id, that doesn't send its events anywhere
+    pub fn detached(mod_id: u32, kind: TaskKind) -> Self {
+        TaskEmitter {
+            window: None,
+            task: TaskRef {
+                task_id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+                mod_id,
+                kind,
+            },
+            control: TaskControl::default(),
+        }
+    }
+
+    /// Lets the task be paused or cancelled through `control`
+    pub fn with_control(mut self, control: TaskControl) -> Self {
+        self.control = control;
+        self
+    }
+
+    pub fn task(&self) -> TaskRef {
+        self.task
+    }
+
+    pub fn control(&self) -> &TaskControl {
+        &self.control
+    }
+
+    pub fn emit(&self, event: ModTaskEvent) {
+        debug!("Emitting `{MOD_TASK_EVENT}`: {:?}", event);
+        if let Some(window) = &self.window {
+            let _ = window.emit(MOD_TASK_EVENT, event);
+        }
+    }
+
+    pub fn queued(&self) {
+        self.emit(ModTaskEvent::Queued(self.task));
+    }
+
+    pub fn started(&self) {
+        self.emit(ModTaskEvent::Started(self.task));
+    }
+
+    pub fn downloading(&self) {
+        self.emit(ModTaskEvent::Downloading(self.task));
+    }
+
+    pub fn progress(&self, progress: DownloadProgress) {
+        self.emit(ModTaskEvent::Progress {
+            task: self.task,
+            progress,
+        });
+    }
+
+    pub fn writing(&self) {
+        self.emit(ModTaskEvent::Writing(self.task));
+    }
+
+    pub fn conflicts(&self, conflicts: Vec<FileConflict>) {
+        self.emit(ModTaskEvent::Conflicts {
+            task: self.task,
+            conflicts,
+        });
+    }
+
+    pub fn missing_dependencies(&self, dependencies: Vec<Requirement>) {
+        self.emit(ModTaskEvent::MissingDependencies {
+            task: self.task,
+            dependencies,
+        });
+    }
+
+    pub fn installing(&self) {
+        self.emit(ModTaskEvent::Installing(self.task));
+    }
+
+    pub fn removing(&self, removed: usize, total: usize) {
+        self.emit(ModTaskEvent::Removing {
+            task: self.task,
+            removed,
+            total,
+        });
+    }
+
+    pub fn done(&self) {
+        self.emit(ModTaskEvent::Done(self.task));
+    }
+
+    /// Sends the event matching how the task ended
+    pub fn finish(&self, result: &Result<(), InstallError>) {
+        match result {
+            Ok(()) => self.done(),
+            Err(InstallError::Paused) => self.emit(ModTaskEvent::Paused(self.task)),
+            Err(InstallError::Cancelled) => self.emit(ModTaskEvent::Cancelled(self.task)),
+            Err(e) => self.failed(e),
+        }
+    }
+
+    pub fn failed(&self, error: &InstallError) {
+        self.emit(ModTaskEvent::Failed {
+            task: self.task,
+            error: error.payload(self.task.mod_id),
+        });
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fs::File, path::Path};
+
+use log::debug;
+use md5::Md5;
+use zip::ZipArchive;
+
+use crate::{
+    cache::{digest_file, sha256_file},
+    lib::error::InstallError,
+};
+
+/// What we expect a downloaded file to look like, from the mod's file metadata on ModWorkshop
+#[derive(Debug, Default, Clone)]
+pub struct ExpectedFile {
+    pub size: Option<u64>,
+    /// A SHA-256 (64 hex characters) or MD5 (32 hex characters) hash, told apart by length
+    pub hash: Option<String>,
+}
+
+/// Checks the size and hash of a downloaded file against what ModWorkshop told us.
+///
+/// A file without a hash we can check is an [`InstallError::Integrity`] error too, it's never
+/// installed unverified.
+///
+/// # Returns
+///
+/// The SHA-256 of the file, as lowercase hex.
+pub fn verify_file(path: &Path, expected: &ExpectedFile) -> Result<String, InstallError> {
+    let size = std::fs::metadata(path)
+        .map_err(InstallError::io("read the downloaded file"))?
+        .len();
+
+    if let Some(expected_size) = expected.size {
+        if size != expected_size {
+            return Err(InstallError::Integrity(format!(
+                "The download is {size} bytes, but should be {expected_size} bytes"
+            )));
+        }
+    }
+
+    let sha256 = sha256_file(path).map_err(InstallError::io("hash the downloaded file"))?;
+
+    let hash = expected.hash.as_deref().map(str::trim).unwrap_or_default();
+    let hex = hash.chars().all(|c| c.is_ascii_hexdigit());
+    let (algorithm, actual) = match hash.len() {
+        64 if hex => ("SHA-256", sha256.clone()),
+        32 if hex => (
+            "MD5",
+            digest_file::<Md5>(path).map_err(InstallError::io("hash the downloaded file"))?,
+        ),
+        0 => {
+            return Err(InstallError::Integrity(
+                "ModWorkshop didn't give a hash to check the download against".to_string(),
+            ))
+        }
+        _ => {
+            return Err(InstallError::Integrity(format!(
+                "Can't check hash {hash:?}, it isn't a SHA-256 or MD5"
+            )))
+        }
+    };
+
+    if !hash.eq_ignore_ascii_case(&actual) {
+        return Err(InstallError::Integrity(format!(
+            "The download's {algorithm} is {actual}, but should be {hash}"
+        )));
+    }
+    debug!("{algorithm} matches ModWorkshop's");
+
+    Ok(sha256)
+}
+
+/// Reads the central directory of a zip, and checks that the data of every file in it is
+/// actually there. Catches truncated and corrupt archives before anything is extracted.
+pub fn validate_zip(path: &Path) -> Result<(), InstallError> {
+    let file = File::open(path).map_err(InstallError::io("open the zip file"))?;
+    let length = file
+        .metadata()
+        .map_err(InstallError::io("read the zip file"))?
+        .len();
+
+    let mut archive = ZipArchive::new(file).map_err(|e| {
+        InstallError::Integrity(format!("The zip's central directory can't be read: {e}"))
+    })?;
+
+    for i in 0..archive.len() {
+        let entry = archive
+            .by_index_raw(i)
+            .map_err(|e| InstallError::Integrity(format!("File {i} of the zip is damaged: {e}")))?;
+
+        if entry.data_start() + entry.compressed_size() > length {
+            return Err(InstallError::Integrity(format!(
+                "The zip is truncated, {} is cut off",
+                entry.name()
+            )));
+        }
+    }
+
+    debug!("{} passed validation", path.display());
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{lib::error::InstallError, registry::InstallTarget};
+
+/// The DLLs SuperBLT is loaded through, either one marks an archive as a copy of the game directory
+const SUPERBLT_DLLS: [&str; 2] = ["IPHLPAPI.dll", "WSOCK32.dll"];
+
+/// How the files in an archive are laid out
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutKind {
+    /// Mod directories, found by their `main.xml` or `mod.txt` (see [`find_mod_roots`])
+    ModRoots,
+    /// The archive mirrors the game directory: `mods/`, `assets/mod_overrides/` and DLLs that go
+    /// next to the game's executable
+    GameTree,
+}
+
+/// What kind of mod a root is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModType {
+    /// A BLT mod, with a `mod.txt`
+    BltMod,
+    /// Plain asset overrides, without a `main.xml`
+    ModOverride,
+    /// A BeardLib mod, with a `main.xml`
+    BeardLibMod,
+    /// A BeardLib custom heist, its `main.xml` declares levels or a narrative
+    BeardLibMap,
+    /// Files that go next to the game's executable, e.g. SuperBLT's DLL
+    GameFiles,
+}
+
+impl ModType {
+    /// Works out what kind of mod the root at `root` is, from its files
+    pub fn detect(root: &Path, target: InstallTarget) -> Self {
+        if target == InstallTarget::Game {
+            return ModType::GameFiles;
+        }
+
+        if let Ok(main_xml) = std::fs::read_to_string(root.join("main.xml")) {
+            let main_xml = main_xml.to_ascii_lowercase();
+            return if main_xml.contains("<level") || main_xml.contains("<narrative") {
+                ModType::BeardLibMap
+            } else {
+                ModType::BeardLibMod
+            };
+        }
+
+        match target {
+            InstallTarget::Mods => ModType::BltMod,
+            _ => ModType::ModOverride,
+        }
+    }
+}
+
+/// A file of the archive, and where it ends up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedFile {
+    /// Relative to the top of the archive
+    pub source: PathBuf,
+    pub destination: PathBuf,
+}
+
+/// A root of the mod, and where it ends up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedRoot {
+    /// Relative to the top of the archive
+    pub source: PathBuf,
+    pub target: InstallTarget,
+    pub mod_type: ModType,
+    pub install_dir: PathBuf,
+    pub files: Vec<PlannedFile>,
+}
+
+/// Where everything in an extracted archive goes in the game directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstallLayout {
+    pub kind: LayoutKind,
+    pub roots: Vec<PlannedRoot>,
+}
+
+impl InstallLayout {
+    /// Classifies the archive extracted to `extracted` and maps every root in it onto `game_dir`.
+    ///
+    /// Archives that mirror the game directory are installed to the matching paths, anything
+    /// else is searched for mod roots.
+    ///
+    /// # Errors
+    ///
+    /// [`InstallError::NoModRoot`] if there is nothing to install, and
+    /// [`InstallError::DuplicateRoot`] if two roots would be installed to the same place.
+    pub fn plan(extracted: &Path, game_dir: &Path) -> Result<Self, InstallError> {
+        let (kind, roots) = match find_game_tree(extracted) {
+            Some(base) => {
+                info!("Archive mirrors the game directory at {:#?}", base);
+                (LayoutKind::GameTree, game_tree_roots(&base))
+            }
+            None => (LayoutKind::ModRoots, find_mod_roots(extracted)),
+        };
+
+        if roots.is_empty() {
+            info!("Nothing to install found in the extracted files.");
+            return Err(InstallError::NoModRoot);
+        }
+
+        let mut planned: Vec<PlannedRoot> = Vec::new();
+        for (root, target) in roots {
+            let install_dir = target
+                .dir(game_dir)
+                .join(root.file_name().ok_or(InstallError::NoModRoot)?);
+
+            if planned.iter().any(|p| p.install_dir == install_dir) {
+                return Err(InstallError::DuplicateRoot(install_dir));
+            }
+
+            let files = walk_files(&root)
+                .into_iter()
+                .map(|file| {
+                    let inside_root = relative_to(&file, &root);
+                    // A file root (e.g. a DLL) is its own destination
+                    let destination = if inside_root.as_os_str().is_empty() {
+                        install_dir.clone()
+                    } else {
+                        install_dir.join(inside_root)
+                    };
+
+                    PlannedFile {
+                        source: relative_to(&file, extracted),
+                        destination,
+                    }
+                })
+                .collect();
+
+            planned.push(PlannedRoot {
+                source: relative_to(&root, extracted),
+                target,
+                mod_type: ModType::detect(&root, target),
+                install_dir,
+                files,
+            });
+        }
+
+        Ok(InstallLayout {
+            kind,
+            roots: planned,
+        })
+    }
+
+    /// Writes the planned file mapping to the log
+    pub fn log(&self) {
+        info!("{:?} layout with {} roots", self.kind, self.roots.len());
+        for root in &self.roots {
+            info!(
+                "{:#?} => {:#?} ({:?}, {:?})",
+                root.source, root.install_dir, root.target, root.mod_type
+            );
+            for file in &root.files {
+                debug!("  {:#?} => {:#?}", file.source, file.destination);
+            }
+        }
+    }
+}
+
+/// Finds the root directories of the mods in an extracted archive, in the order they were found:
+///
+/// * directories holding `main.xml` (installed to `mod_overrides`)
+/// * directories holding `mod.txt` (installed to `mods`)
+/// * every directory inside a `mod_overrides` directory (installed to `mod_overrides`)
+///
+/// The search is breadth first and doesn't look inside a root once it's found, so a mod's own
+/// subdirectories are never mistaken for separate mods.
+pub fn find_mod_roots(extracted: &Path) -> Vec<(PathBuf, InstallTarget)> {
+    let mut roots = Vec::new();
+
+    let mut pending = vec![extracted.to_path_buf()];
+    while !pending.is_empty() {
+        // Breadth first, so shallower roots are found first
+        let mut next = Vec::new();
+        for dir in pending {
+            let (subdirs, files) = read_sorted(&dir);
+            let has_file = |name: &str| files.iter().any(|f| f.file_name() == Some(name.as_ref()));
+
+            if has_file("main.xml") {
+                debug!("Found xml in {:#?}", dir);
+                roots.push((dir, InstallTarget::ModOverrides));
+            } else if has_file("mod.txt") {
+                debug!("Found mod file in {:#?}", dir);
+                roots.push((dir, InstallTarget::Mods));
+            } else if dir != extracted && is_named(&dir, "mod_overrides") {
+                debug!("Found mod_overrides directory {:#?}", dir);
+                roots.extend(
+                    subdirs
+                        .into_iter()
+                        .map(|d| (d, InstallTarget::ModOverrides)),
+                );
+            } else {
+                next.extend(subdirs);
+            }
+        }
+        pending = next;
+    }
+
+    roots
+}
+
+/// Finds the directory of an extracted archive that stands for the game directory: the first one
+/// (breadth first) holding a `mods` or `assets/mod_overrides` directory, or one of SuperBLT's DLLs.
+///
+/// Mod roots aren't searched, a mod's own `assets` directory doesn't make it a game directory.
+pub fn find_game_tree(extracted: &Path) -> Option<PathBuf> {
+    let mut pending = vec![extracted.to_path_buf()];
+    while !pending.is_empty() {
+        let mut next = Vec::new();
+        for dir in pending {
+            let (subdirs, files) = read_sorted(&dir);
+
+            let is_mod_root = files
+                .iter()
+                .any(|f| is_named(f, "main.xml") || is_named(f, "mod.txt"));
+            if is_mod_root {
+                continue;
+            }
+
+            let has_mods = subdirs.iter().any(|d| is_named(d, "mods"));
+            let has_overrides = subdirs
+                .iter()
+                .filter(|d| is_named(d, "assets"))
+                .any(|assets| find_child(assets, "mod_overrides").is_some());
+            let has_dll = files
+                .iter()
+                .any(|f| SUPERBLT_DLLS.iter().any(|dll| is_named(f, dll)));
+
+            if has_mods || has_overrides || has_dll {
+                return Some(dir);
+            }
+            next.extend(subdirs);
+        }
+        pending = next;
+    }
+
+    None
+}
+
+/// The roots of an archive that mirrors the game directory at `base`: every directory in `mods`
+/// and `assets/mod_overrides`, and every DLL next to where the executable would be.
+///
+/// Anything else (readmes, loose files in `mods`) isn't installed.
+fn game_tree_roots(base: &Path) -> Vec<(PathBuf, InstallTarget)> {
+    let mut roots = Vec::new();
+    let (subdirs, files) = read_sorted(base);
+
+    for file in files {
+        let is_dll = file
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dll"));
+        if is_dll {
+            roots.push((file, InstallTarget::Game));
+        } else {
+            debug!("Not installing {:#?}", file);
+        }
+    }
+
+    for dir in subdirs {
+        let mod_dirs = if is_named(&dir, "mods") {
+            Some((dir.clone(), InstallTarget::Mods))
+        } else if is_named(&dir, "assets") {
+            find_child(&dir, "mod_overrides").map(|d| (d, InstallTarget::ModOverrides))
+        } else {
+            None
+        };
+
+        let Some((mod_dirs, target)) = mod_dirs else {
+            debug!("Not installing {:#?}", dir);
+            continue;
+        };
+
+        let (mods, loose) = read_sorted(&mod_dirs);
+        for file in loose {
+            warn!("Not installing {:#?}, it isn't in a mod directory", file);
+        }
+        roots.extend(mods.into_iter().map(|d| (d, target)));
+    }
+
+    roots
+}
+
+/// Lists the subdirectories and files in `dir`, each sorted by name
+fn read_sorted(dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    paths.into_iter().partition(|path| path.is_dir())
+}
+
+/// Finds the subdirectory `name` of `dir`, ignoring case
+fn find_child(dir: &Path, name: &str) -> Option<PathBuf> {
+    read_sorted(dir).0.into_iter().find(|d| is_named(d, name))
+}
+
+/// Whether the last component of `path` is `name`, ignoring case
+fn is_named(path: &Path, name: &str) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+}
+
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    path.strip_prefix(base).unwrap_or(path).to_path_buf()
+}
+
+/// Recursively lists every file under `root`, or just `root` if it's a file
+pub(crate) fn walk_files(root: &Path) -> Vec<PathBuf> {
+    if root.is_file() {
+        return vec![root.to_path_buf()];
+    }
+
+    let (subdirs, mut files) = read_sorted(root);
+    for dir in subdirs {
+        files.extend(walk_files(&dir));
+    }
+    files
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use log::warn;
+use roxmltree::{Document, Node};
+use serde::{Deserialize, Serialize};
+
+/// The metadata of a BeardLib mod, read from its `main.xml`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MainXml {
+    pub name: Option<String>,
+    /// The global the mod is reachable through in Lua
+    pub global_key: Option<String>,
+    /// Mods with a higher priority are loaded first
+    pub priority: Option<i64>,
+    pub asset_updates: Option<AssetUpdates>,
+    #[serde(default)]
+    pub dependencies: Vec<MainXmlDependency>,
+}
+
+/// Where BeardLib checks for updates of the mod, from `<AssetUpdates>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssetUpdates {
+    /// Usually `modworkshop`
+    pub provider: Option<String>,
+    /// The id of the mod on the provider
+    pub id: Option<String>,
+    pub version: Option<String>,
+}
+
+/// A mod declared in `<Dependencies>`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MainXmlDependency {
+    pub name: String,
+    pub provider: Option<String>,
+    pub id: Option<String>,
+    pub min_version: Option<String>,
+}
+
+impl MainXml {
+    /// Reads the `main.xml` at `path`, `None` if there isn't one or it isn't valid XML
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        match Self::parse(&String::from_utf8_lossy(&data)) {
+            Ok(main_xml) => Some(main_xml),
+            Err(e) => {
+                warn!("Ignoring unreadable \"{}\": {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Parses the contents of a `main.xml`.
+    ///
+    /// The metadata is in the attributes of the root element (`<table>` or `<mod>`), element
+    /// names are matched ignoring case as BeardLib does.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document =
+            Document::parse(text.trim_start_matches('\u{feff}')).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+
+        let asset_updates = find_element(root, "AssetUpdates").map(|updates| AssetUpdates {
+            provider: attribute(updates, "provider"),
+            id: attribute(updates, "id"),
+            version: attribute(updates, "version"),
+        });
+
+        let dependencies = find_element(root, "Dependencies")
+            .into_iter()
+            .flat_map(|dependencies| dependencies.children().filter(Node::is_element))
+            .map(|dependency| MainXmlDependency {
+                // `<dependency name="..."/>`, or just `<BeardLib/>`
+                name: attribute(dependency, "name")
+                    .unwrap_or_else(|| dependency.tag_name().name().to_string()),
+                provider: attribute(dependency, "provider"),
+                id: attribute(dependency, "id"),
+                min_version: attribute(dependency, "min_ver")
+                    .or_else(|| attribute(dependency, "version")),
+            })
+            .collect();
+
+        Ok(MainXml {
+            name: attribute(root, "name"),
+            global_key: attribute(root, "global_key"),
+            priority: attribute(root, "priority").and_then(|p| p.trim().parse().ok()),
+            asset_updates,
+            dependencies,
+        })
+    }
+}
+
+/// The first element named `name` under `node`, ignoring case
+fn find_element<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.descendants()
+        .find(|n| n.is_element() && n.tag_name().name().eq_ignore_ascii_case(name))
+}
+
+/// An attribute of `node`, ignoring case and empty values
+fn attribute(node: Node, name: &str) -> Option<String> {
+    node.attributes()
+        .find(|a| a.name().eq_ignore_ascii_case(name))
+        .map(|a| a.value().trim().to_string())
+        .filter(|value| !value.is_empty())
+}
@@ -1,168 +1,230 @@
-use std::{fs::{create_dir_all, File}, io, path::{Path, PathBuf}};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
 
 use futures::future::BoxFuture;
 use log::{debug, info};
+use serde::Serialize;
 use tokio::fs;
-use zip::ZipArchive;
 
-use crate::settings::load_settings;
+use crate::{
+    games::payday2,
+    registry::{InstallTarget, InstalledMod, ModRelease, OverwrittenFile},
+};
 
-/// Asynchronously unzips a mod file and extracts its contents to specific directories.
+use self::{
+    archive::ArchiveFormat, error::InstallError, layout::InstallLayout, storage::StorageLayout,
+    uninstall::remove_mod_files,
+};
+
+pub mod archive;
+pub mod dependencies;
+pub mod download;
+pub mod error;
+pub mod events;
+pub mod integrity;
+pub mod layout;
+pub mod main_xml;
+pub mod mod_txt;
+pub mod plan;
+pub mod priority;
+pub mod storage;
+pub mod toggle;
+pub mod uninstall;
+pub mod update;
+
+/// A mod root that [`install_staged_mod`] put into the game directory
+#[derive(Serialize, Debug, Clone)]
+pub struct ExtractedRoot {
+    /// Where the root was in the archive, relative to the top of it
+    pub source: PathBuf,
+    pub target: InstallTarget,
+    pub install_dir: PathBuf,
+    /// Every file and directory that was written
+    pub files: Vec<PathBuf>,
+    /// The files in `files` that were there before, see [`InstalledMod::overwrote`]
+    pub overwrote: Vec<OverwrittenFile>,
+}
+
+/// What [`install_staged_mod`] put into the game directory, one entry for every mod root in the archive
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ExtractedMod {
+    pub roots: Vec<ExtractedRoot>,
+}
+
+/// Copies the roots of an archive staged by [`stage_mod`] into the game directory, exactly as
+/// `layout` says, then removes the staging directory.
 ///
 /// # Arguments
 ///
-/// * `fname` - The path to the zip file to be unzipped.
-/// * `mod_id` - The ID of the mod being unzipped.
-///
-/// # Panics
-///
-/// This function will panic if it fails to open the zip file or read the zip archive.
+/// * `mod_dir` - The staging directory the archive was extracted to.
+/// * `layout` - Where everything in `mod_dir` goes, from [`InstallLayout::plan`].
+/// * `mod_id` - The ID of the mod being installed.
+/// * `replaced_dir` - Where the files the mod overwrites are backed up, see
+///   [`StorageLayout::replaced_dir`].
 ///
 /// # Errors
 ///
-/// This function will return an error if it fails to create directories or copy files.
+/// This function will return an [`InstallError`] if it fails to create directories or copy files.
+/// Everything that was already copied, including the part of the root that failed, is removed again.
 ///
-/// # Safety
+/// # Returns
 ///
-/// This function involves file operations and directory creations, so care must be taken to handle errors appropriately.
+/// The install target, root directory and written files of every root of the mod.
 ///
 /// # Examples
 ///
 /// ```
-/// // Example usage of the `unzip_mod` function
-/// unzip_mod(PathBuf::from("mod.zip"), 123).await;
+/// let (mod_dir, layout) = stage_mod(&PathBuf::from("mod.7z"), 123).await?;
+/// let extracted = install_staged_mod(&mod_dir, layout, 123, &storage.replaced_dir(123)).await?;
 /// ```
-/// # TODO:
-/// Make this more generic, e.g. being able to 
-pub async fn unzip_mod(fname: PathBuf, mod_id: u32) {
-    info!("Trying to unzip file: {:#?}", fname);
-    let file = File::open(&fname).expect("Failed to open the zip file");
-    let mut archive = ZipArchive::new(file).expect("Failed to read the zip archive");
-
-    let active_settings = load_settings().await.unwrap();
-
-    let mod_dir = PathBuf::from(format!(
-        "{}/pd2/{mod_id}",
-        active_settings
-            .download_path
-            .expect("Expected download path")
-            .display()
-    ));
-
-    let mut steam_dir = steamlocate::SteamDir::locate().unwrap();
-    let app = &steam_dir.app(&218620).unwrap();
-
-    let target_directory = PathBuf::from(format!("{}/mods", &app.path.display()));
-    let xml_target_dir = PathBuf::from(format!("{}/assets/mod_overrides", &app.path.display()));
-
-    // Ensure the mod directory exists
-    if !mod_dir.exists() {
-        fs::create_dir_all(&mod_dir)
-            .await
-            .expect("Failed to create mod directory");
-    }
+pub async fn install_staged_mod(
+    mod_dir: &Path,
+    layout: InstallLayout,
+    mod_id: u32,
+    replaced_dir: &Path,
+) -> Result<ExtractedMod, InstallError> {
+    layout.log();
 
-    let mut main_xml_path: Option<PathBuf> = None;
-    let mut mod_txt_path: Option<PathBuf> = None;
-
-    for i in 0..archive.len() {
-        let mut inner_file = archive
-            .by_index(i)
-            .expect("Failed to access file in archive");
-        let outpath = match inner_file.enclosed_name() {
-            Some(path) => mod_dir.join(path),
-            None => continue,
-        };
-
-        if inner_file.is_dir() {
-            info!("File {i} extracted to \"{}\"", outpath.display());
-            create_dir_all(&outpath).expect("Failed to create directory");
-        } else {
-            info!(
-                "File {i} extracted to \"{}\" ({} bytes)",
-                outpath.display(),
-                inner_file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    create_dir_all(p).expect("Failed to create parent directory");
-                }
-            }
+    let mut extracted = ExtractedMod::default();
+    for root in layout.roots {
+        let source = mod_dir.join(&root.source);
+        info!("Target path: {}", root.install_dir.display());
 
-            let mut outfile = File::create(&outpath).expect("Failed to create file");
-            io::copy(&mut inner_file, &mut outfile).expect("Failed to copy file content");
+        let mut written = Written::default();
+        let copied =
+            copy_all_into(&source, &root.install_dir, Some(replaced_dir), &mut written).await;
+        extracted.roots.push(ExtractedRoot {
+            source: root.source,
+            target: root.target,
+            install_dir: root.install_dir,
+            files: written.paths,
+            overwrote: written.overwrote,
+        });
 
-            // Check if the current file is `main.xml`
-            if outpath.file_name() == Some("main.xml".as_ref()) {
-                main_xml_path = Some(outpath.parent().unwrap().to_path_buf());
-                debug!("Found xml {:#?}", main_xml_path);
-            } else if outpath.file_name() == Some("mod.txt".as_ref()) {
-                mod_txt_path = Some(outpath.parent().unwrap().to_path_buf());
-                debug!("Found mod file");
+        if let Err(e) = copied {
+            // Don't leave half of the archive installed, including what this root got to
+            let copied = InstalledMod::from_extracted(
+                mod_id,
+                String::new(),
+                ModRelease::default(),
+                extracted,
+            );
+            if let Some(copied) = copied {
+                let _ = remove_mod_files(&copied, |_, _| {}).await;
             }
+            return Err(InstallError::io("copy the mod into the game directory")(e));
         }
+        info!("Copied mod directory \"{}\"", source.display());
     }
 
-    // If we found main.xml, move its parent directory to the target directory
-    if let Some(mod_root_dir) = main_xml_path {
-        let target_path = xml_target_dir.join(mod_root_dir.file_name().unwrap());
+    fs::remove_dir_all(mod_dir)
+        .await
+        .map_err(InstallError::io("clean up"))?;
 
-        info!("Target path: {}", target_path.display());
+    info!(
+        "Unzipping done! Installed {} mod roots",
+        extracted.roots.len()
+    );
+    Ok(extracted)
+}
 
-        if mod_root_dir.exists() {
-            info!("Cross-device move detected, copying mod_overrides directory...");
-            copy_all_cross_device(&mod_root_dir, &target_path)
-                .await
-                .expect("Failed to copy mods cross-device");
+/// Extracts `fname` into the staging directory of the mod and works out where everything in it
+/// would be installed, without touching the game directory.
+///
+/// # Returns
+///
+/// The staging directory, and the layout [`install_staged_mod`] installs it with.
+pub async fn stage_mod(
+    fname: &Path,
+    mod_id: u32,
+) -> Result<(PathBuf, InstallLayout), InstallError> {
+    info!("Trying to extract file: {:#?}", fname);
+    let format = ArchiveFormat::detect(fname).ok_or_else(|| {
+        InstallError::UnsupportedArchive(
+            fname
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        )
+    })?;
 
-            fs::remove_dir_all(&mod_root_dir)
-                .await
-                .expect("Failed to clean up.");
+    let storage = StorageLayout::load(payday2::GAME_KEY)
+        .await
+        .map_err(InstallError::Settings)?;
+    let mod_dir = storage.staging_dir(mod_id);
 
-            info!(
-                "Moved mod directory from \"{}\" to \"{}\"",
-                mod_root_dir.display(),
-                target_path.display()
-            );
-        }
-    } else if let Some(mod_root_dir) = mod_txt_path {
-        let target_path = target_directory.join(mod_root_dir.file_name().unwrap());
-
-        if mod_root_dir.exists() {
-            info!("Cross-device move detected, copying mod directory...");
-            copy_all_cross_device(&mod_root_dir, &target_path)
-                .await
-                .expect("Failed to copy mods cross-device");
-
-            fs::remove_dir_all(&mod_root_dir)
-                .await
-                .expect("Failed to clean up.");
-
-            info!(
-                "Moved mod directory from \"{}\" to \"{}\"",
-                mod_root_dir.display(),
-                target_path.display()
-            );
-        }
-    } else {
-        info!("main.xml not found in the extracted files.");
+    let game_dir = payday2::game_dir()?;
+
+    // Whatever was planned for the previous contents of the staging directory no longer applies
+    let _ = fs::remove_file(storage.plan_path(mod_id)).await;
+
+    // Start from an empty directory, so nothing from an earlier attempt is mistaken for part of the mod
+    if mod_dir.exists() {
+        fs::remove_dir_all(&mod_dir)
+            .await
+            .map_err(InstallError::io("clear the mod directory"))?;
     }
 
-    info!("Unzipping done!");
+    format.extract(fname, &mod_dir)?;
+
+    let layout = InstallLayout::plan(&mod_dir, &game_dir)?;
+    Ok((mod_dir, layout))
 }
 
 /// Asynchronously copies all files and directories from the source path to the destination path.
-/// If the destination path does not exist, it will be created.
+/// If the destination path does not exist, it will be created. If the source is a file, it's copied
+/// to the destination path.
 ///
 /// # Arguments
 /// * `source` - The source path to copy files and directories from.
 /// * `destination` - The destination path to copy files and directories to.
 ///
 /// # Returns
-/// An `io::Result` indicating the result of the copy operation.
-pub async fn copy_all_cross_device(source: &Path, destination: &Path) -> io::Result<()> {
+/// An `io::Result` containing every file and directory that was written at the destination.
+/// Files that were already there are overwritten without a backup, use [`copy_all_into`] to keep them.
+pub async fn copy_all_cross_device(source: &Path, destination: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut written = Written::default();
+    copy_all_into(source, destination, None, &mut written).await?;
+    Ok(written.paths)
+}
+
+/// What [`copy_all_into`] wrote so far
+#[derive(Debug, Default)]
+pub struct Written {
+    /// Every file and directory that was created or overwritten at the destination
+    pub paths: Vec<PathBuf>,
+    /// The files in `paths` that were there before, and where their previous contents went
+    pub overwrote: Vec<OverwrittenFile>,
+}
+
+/// Like [`copy_all_cross_device`], but adds what it writes to `written` as it goes, so the
+/// caller knows what to clean up when the copy fails part way.
+///
+/// # Arguments
+/// * `source` - The source path to copy files and directories from.
+/// * `destination` - The destination path to copy files and directories to.
+/// * `backup_dir` - Files that are overwritten are copied under here first (by their full path),
+///   `None` to just overwrite them.
+/// * `written` - Every file and directory that was written at the destination is added to it.
+pub async fn copy_all_into(
+    source: &Path,
+    destination: &Path,
+    backup_dir: Option<&Path>,
+    written: &mut Written,
+) -> io::Result<()> {
+    // A root can be a single file (see `InstallTarget::Game`)
+    if source.is_file() {
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        return copy_file(source, destination, backup_dir, written).await;
+    }
+
     if !destination.exists() {
         tokio::fs::create_dir_all(destination).await?;
+        written.paths.push(destination.to_path_buf());
     }
 
     let mut entries = tokio::fs::read_dir(source).await?;
@@ -171,15 +233,59 @@ pub async fn copy_all_cross_device(source: &Path, destination: &Path) -> io::Res
         let dest_path = destination.join(entry.file_name());
 
         if path.is_dir() {
-            box_copy_all_cross_device(path, dest_path).await?;
+            box_copy_all_into(path, dest_path, backup_dir, written).await?;
         } else {
-            fs::copy(&path, &dest_path).await?;
+            copy_file(&path, &dest_path, backup_dir, written).await?;
         }
     }
 
     Ok(())
 }
 
+/// Copies a single file, backing up what was at `destination` into `backup_dir` first.
+///
+/// A backup that is already there is kept, it holds the contents from before the mod ever
+/// touched the file (e.g. when a rollback copies the mod back over a restored file).
+async fn copy_file(
+    source: &Path,
+    destination: &Path,
+    backup_dir: Option<&Path>,
+    written: &mut Written,
+) -> io::Result<()> {
+    if let (true, Some(backup_dir)) = (destination.is_file(), backup_dir) {
+        let backup = backup_path(backup_dir, destination);
+        if !backup.exists() {
+            debug!(
+                "Backing up \"{}\" before overwriting it",
+                destination.display()
+            );
+            if let Some(parent) = backup.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::copy(destination, &backup).await?;
+        }
+        written.overwrote.push(OverwrittenFile {
+            path: destination.to_path_buf(),
+            backup,
+        });
+    }
+
+    fs::copy(source, destination).await?;
+    written.paths.push(destination.to_path_buf());
+    Ok(())
+}
+
+/// Where the backup of `file` goes in `backup_dir`: under its full path, so files from anywhere
+/// in the game can't collide
+pub fn backup_path(backup_dir: &Path, file: &Path) -> PathBuf {
+    let mut backup = backup_dir.to_path_buf();
+    backup.extend(file.components().filter_map(|component| match component {
+        Component::Normal(part) => Some(part),
+        _ => None,
+    }));
+    backup
+}
+
 /// Asynchronously copies all files and directories from the source path to the destination path.
 ///
 /// If the destination path does not exist, it will be created.
@@ -187,12 +293,13 @@ pub async fn copy_all_cross_device(source: &Path, destination: &Path) -> io::Res
 /// # Arguments
 /// * `source` - The source path from which to copy files and directories.
 /// * `destination` - The destination path to which files and directories will be copied.
-///
-/// # Returns
-/// An `io::Result` indicating the result of the copy operation.
-pub fn box_copy_all_cross_device(
+/// * `backup_dir` - Where overwritten files are backed up, see [`copy_all_into`].
+/// * `written` - Every file and directory that was written at the destination is added to it.
+pub fn box_copy_all_into<'a>(
     source: PathBuf,
     destination: PathBuf,
-) -> BoxFuture<'static, io::Result<()>> {
-    Box::pin(async move { copy_all_cross_device(&source, &destination).await })
+    backup_dir: Option<&'a Path>,
+    written: &'a mut Written,
+) -> BoxFuture<'a, io::Result<()>> {
+    Box::pin(async move { copy_all_into(&source, &destination, backup_dir, written).await })
 }
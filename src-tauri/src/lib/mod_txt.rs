@@ -0,0 +1,224 @@
+use std::path::Path;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The metadata of a BLT mod, read from its `mod.txt`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ModTxt {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    /// The BLT version the mod was made for, SuperBLT still loads mods made for `"2"`
+    pub blt_version: Option<String>,
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    #[serde(default)]
+    pub persist_scripts: Vec<PersistScript>,
+    /// Where SuperBLT checks for updates of the mod (or of parts of it)
+    #[serde(default)]
+    pub updates: Vec<UpdateSource>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A script run after a game script is loaded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hook {
+    /// The game script it hooks, e.g. `lib/managers/menumanager`
+    pub hook_id: String,
+    pub script_path: String,
+}
+
+/// A script run every frame while `global` isn't set
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PersistScript {
+    pub global: String,
+    pub script_path: String,
+}
+
+/// An entry of the `updates` block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UpdateSource {
+    pub identifier: String,
+    pub display_name: Option<String>,
+    /// The `meta` URL of the `host` the update is checked against
+    pub meta_url: Option<String>,
+    /// Where the update is installed, relative to the game directory (`mods` if not set)
+    pub install_dir: Option<String>,
+    pub install_folder: Option<String>,
+}
+
+/// A mod this mod needs, from the `dependencies` block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub identifier: String,
+    pub display_name: Option<String>,
+    pub download_url: Option<String>,
+    pub meta_url: Option<String>,
+}
+
+impl ModTxt {
+    /// Reads the `mod.txt` at `path`, `None` if there isn't one or it can't be made sense of
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        match Self::parse(&String::from_utf8_lossy(&data)) {
+            Ok(mod_txt) => Some(mod_txt),
+            Err(e) => {
+                warn!("Ignoring unreadable \"{}\": {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Parses the contents of a `mod.txt`.
+    ///
+    /// Modders write these by hand, so comments, trailing commas and numbers where strings are
+    /// expected (`"version": 1.2`) are accepted, and anything of the wrong shape is skipped.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(&strict_json(text)).map_err(|e| e.to_string())?;
+        let object = value.as_object().ok_or("mod.txt isn't an object")?;
+
+        Ok(ModTxt {
+            name: object.get("name").and_then(text_of),
+            description: object.get("description").and_then(text_of),
+            author: object.get("author").and_then(text_of),
+            version: object.get("version").and_then(text_of),
+            blt_version: object.get("blt_version").and_then(text_of),
+            hooks: entries(object.get("hooks"))
+                .filter_map(|hook| {
+                    Some(Hook {
+                        hook_id: hook.get("hook_id").and_then(text_of)?,
+                        script_path: hook.get("script_path").and_then(text_of)?,
+                    })
+                })
+                .collect(),
+            persist_scripts: entries(object.get("persist_scripts"))
+                .filter_map(|script| {
+                    Some(PersistScript {
+                        global: script.get("global").and_then(text_of)?,
+                        script_path: script.get("script_path").and_then(text_of)?,
+                    })
+                })
+                .collect(),
+            updates: entries(object.get("updates"))
+                .filter_map(|update| {
+                    Some(UpdateSource {
+                        identifier: update.get("identifier").and_then(text_of)?,
+                        display_name: update.get("display_name").and_then(text_of),
+                        meta_url: update
+                            .get("host")
+                            .and_then(|host| host.get("meta"))
+                            .and_then(text_of),
+                        install_dir: update.get("install_dir").and_then(text_of),
+                        install_folder: update.get("install_folder").and_then(text_of),
+                    })
+                })
+                .collect(),
+            dependencies: dependencies(object.get("dependencies")),
+        })
+    }
+}
+
+/// The objects in an array, anything else in it is skipped
+fn entries(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.is_object())
+}
+
+/// Dependencies are either an array of objects with an `identifier`, or an object keyed by
+/// identifier whose values hold the details (or are just the download URL)
+fn dependencies(value: Option<&Value>) -> Vec<Dependency> {
+    let details = |identifier: String, value: &Value| Dependency {
+        identifier,
+        display_name: value.get("display_name").and_then(text_of),
+        download_url: value
+            .get("download_url")
+            .and_then(text_of)
+            .or_else(|| value.as_str().map(str::to_string)),
+        meta_url: value.get("meta").and_then(text_of),
+    };
+
+    match value {
+        Some(Value::Array(_)) => entries(value)
+            .filter_map(|entry| Some(details(entry.get("identifier").and_then(text_of)?, entry)))
+            .collect(),
+        Some(Value::Object(map)) => map
+            .iter()
+            .map(|(identifier, entry)| details(identifier.clone(), entry))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Strings as they are, and numbers and booleans written out
+fn text_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Turns the loose JSON in `mod.txt` files into JSON `serde_json` accepts, by dropping a byte
+/// order mark, `//` and `/* */` comments, and commas before a closing bracket
+fn strict_json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push(c);
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                out.push(' ');
+            }
+            '}' | ']' => {
+                // Drop a trailing comma, along with the whitespace after it
+                let trimmed = out.trim_end().len();
+                if out[..trimmed].ends_with(',') {
+                    out.truncate(trimmed - 1);
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
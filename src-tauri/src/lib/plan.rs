@@ -0,0 +1,95 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conflicts::FileConflict,
+    lib::{dependencies::Requirement, layout::InstallLayout},
+    registry::ModRelease,
+};
+
+/// What installing a mod would do, made by `plan_install` so the user can confirm it first.
+///
+/// The plan is saved next to the staged (extracted) archive, and the install that follows
+/// executes it as is, as long as it's for the same archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstallPlan {
+    pub mod_id: u32,
+    pub name: String,
+    #[serde(flatten)]
+    pub release: ModRelease,
+    /// SHA-256 of the archive the plan was made from
+    pub sha256: String,
+    /// The roots of the mod, what kind of mod each one is, and where each of their files goes
+    pub layout: InstallLayout,
+    /// Files that already exist in the game directory and would be replaced
+    pub overwrites: Vec<PathBuf>,
+    /// Paths that other installed mods write too
+    pub conflicts: Vec<FileConflict>,
+    /// Mods it needs that aren't installed, see `plan_dependencies`
+    pub missing_dependencies: Vec<Requirement>,
+}
+
+impl InstallPlan {
+    pub fn new(
+        mod_id: u32,
+        name: String,
+        release: ModRelease,
+        sha256: String,
+        layout: InstallLayout,
+        conflicts: Vec<FileConflict>,
+        missing_dependencies: Vec<Requirement>,
+    ) -> Self {
+        let overwrites = layout
+            .roots
+            .iter()
+            .flat_map(|root| &root.files)
+            .map(|file| file.destination.clone())
+            .filter(|destination| destination.exists())
+            .collect();
+
+        InstallPlan {
+            mod_id,
+            name,
+            release,
+            sha256,
+            layout,
+            overwrites,
+            conflicts,
+            missing_dependencies,
+        }
+    }
+
+    /// Loads the plan saved at `path`, `None` if there isn't one (or it can't be read)
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = fs::read_to_string(path).ok()?;
+        match serde_json::from_str(&data) {
+            Ok(plan) => Some(plan),
+            Err(e) => {
+                info!("Ignoring unreadable install plan {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        debug!("Saving install plan for mod {}...", self.mod_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Whether every file the plan installs is still in the staging directory `staged`
+    pub fn is_staged(&self, staged: &Path) -> bool {
+        self.layout
+            .roots
+            .iter()
+            .flat_map(|root| &root.files)
+            .all(|file| staged.join(&file.source).exists())
+    }
+}
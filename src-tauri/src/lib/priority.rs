@@ -0,0 +1,183 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    conflicts::ConflictIndex,
+    lib::{storage::StorageLayout, toggle::move_dir},
+    profiles::ProfileStore,
+    registry::{InstalledMod, ModRegistry},
+    settings::load_settings,
+};
+
+/// The files that have to move for only the winning copy of every conflicting asset to be in the
+/// game directory, as `(mod id, installed path)`
+#[derive(Debug, Default, PartialEq)]
+pub struct Deployment {
+    /// Losing copies to move into the manager's store
+    pub stash: Vec<(u32, PathBuf)>,
+    /// Copies in the store that no longer lose, to move back into the game
+    pub restore: Vec<(u32, PathBuf)>,
+}
+
+/// Where `id` ranks for conflicts, lower wins.
+///
+/// Mods in `priority` win over the mods after them and over mods that aren't listed. Of the mods
+/// that aren't listed, the one installed last wins, as its files were the last ones written.
+fn rank(registry: &ModRegistry, priority: &[u32], id: u32) -> (usize, Reverse<u64>) {
+    let position = priority.iter().position(|p| *p == id).unwrap_or(usize::MAX);
+    let installed_at = registry.get(id).map_or(0, |m| m.installed_at);
+    (position, Reverse(installed_at))
+}
+
+/// Works out which files have to move so that, for every asset overridden by more than one
+/// enabled mod, only the copy of the highest priority mod is in the game directory.
+///
+/// Only the files whose winner changed are included, so re-ordering `priority` only moves the
+/// files of the mods that were re-ordered.
+pub fn plan_deployment(registry: &ModRegistry, priority: &[u32]) -> Deployment {
+    let index = ConflictIndex::build(registry);
+
+    let mut losers: BTreeSet<(u32, PathBuf)> = BTreeSet::new();
+    for (key, writers) in index.asset_conflicts() {
+        let Some(winner) = writers
+            .keys()
+            .copied()
+            .min_by_key(|id| rank(registry, priority, *id))
+        else {
+            continue;
+        };
+        debug!("Mod {winner} wins {:?}", key);
+
+        losers.extend(
+            writers
+                .iter()
+                .filter(|(id, _)| **id != winner)
+                .map(|(id, file)| (*id, file.clone())),
+        );
+    }
+
+    let mut deployment = Deployment::default();
+    for installed in registry.mods.values().filter(|m| m.is_enabled()) {
+        for file in &installed.overridden {
+            if !losers.contains(&(installed.id, file.clone())) {
+                deployment.restore.push((installed.id, file.clone()));
+            }
+        }
+    }
+    for (id, file) in losers {
+        let stashed = registry
+            .get(id)
+            .is_some_and(|m| m.overridden.contains(&file));
+        if !stashed {
+            deployment.stash.push((id, file));
+        }
+    }
+
+    deployment
+}
+
+/// Where the overridden copy of `file` is kept: under the name of the root it's in, so the roots
+/// of a mod don't collide
+fn stored_path(installed: &InstalledMod, storage: &StorageLayout, file: &Path) -> Option<PathBuf> {
+    let root = installed
+        .roots()
+        .into_iter()
+        .find(|root| file.starts_with(&root.install_dir))?;
+    let relative = file.strip_prefix(root.install_dir.parent()?).ok()?;
+    Some(storage.overridden_dir(installed.id).join(relative))
+}
+
+/// Moves the losing copies of conflicting assets into the store and the ones that no longer lose
+/// back into the game, recording it in `registry` (which is left for the caller to save).
+///
+/// If a move fails the registry still matches what was moved before it.
+///
+/// # Returns
+///
+/// The number of files that were moved.
+pub async fn deploy(
+    registry: &mut ModRegistry,
+    priority: &[u32],
+    storage: &StorageLayout,
+) -> Result<usize, String> {
+    let deployment = plan_deployment(registry, priority);
+    if deployment.stash.is_empty() && deployment.restore.is_empty() {
+        return Ok(0);
+    }
+
+    let mut moved = 0;
+    for (id, file) in deployment.restore {
+        let Some(installed) = registry.mods.get_mut(&id) else {
+            continue;
+        };
+        let stored = stored_path(installed, storage, &file);
+
+        match stored {
+            Some(stored) if stored.exists() => {
+                debug!("Restoring \"{}\"", file.display());
+                move_dir(&stored, &file)
+                    .await
+                    .map_err(|e| format!("Failed to restore \"{}\": {e}", file.display()))?;
+                moved += 1;
+            }
+            _ => warn!(
+                "The overridden copy of \"{}\" is gone, forgetting about it",
+                file.display()
+            ),
+        }
+        installed.overridden.retain(|f| f != &file);
+    }
+
+    for (id, file) in deployment.stash {
+        let Some(installed) = registry.mods.get_mut(&id) else {
+            continue;
+        };
+        let Some(stored) = stored_path(installed, storage, &file) else {
+            warn!("\"{}\" isn't in any root of mod {id}", file.display());
+            continue;
+        };
+
+        if file.exists() {
+            debug!("Moving overridden \"{}\" to the store", file.display());
+            move_dir(&file, &stored)
+                .await
+                .map_err(|e| format!("Failed to move \"{}\" aside: {e}", file.display()))?;
+            moved += 1;
+        }
+        installed.overridden.push(file);
+    }
+
+    info!("Deployed conflict priorities, moved {moved} files");
+    Ok(moved)
+}
+
+/// The priority list of the active profile of `game`, empty if there isn't one
+pub async fn active_priority(game: &str) -> Result<Vec<u32>, String> {
+    let settings = load_settings().await?;
+    let Some(active) = settings
+        .active_profiles
+        .and_then(|active| active.get(game).cloned())
+    else {
+        return Ok(Vec::new());
+    };
+
+    Ok(ProfileStore::load(game)?
+        .get(&active)
+        .map(|profile| profile.priority.clone())
+        .unwrap_or_default())
+}
+
+/// Deploys the priorities of the active profile of `game` to the mods in `registry`, and saves it
+pub async fn redeploy(game: &str, registry: &mut ModRegistry) -> Result<usize, String> {
+    let priority = active_priority(game).await?;
+    let storage = StorageLayout::load(game).await?;
+
+    let result = deploy(registry, &priority, &storage).await;
+    registry.save()?;
+    result
+}
@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+use crate::{games::check_game_key, settings::load_settings};
+
+/// Where the mod manager keeps the files of a game, everything lives under `{download_path}/{game}`:
+///
+/// * `archives/` - archives that are being downloaded (and `.part` files of unfinished downloads,
+///   named after the mod and file id)
+/// * `cache/` - finished archives, see [`ArchiveCache`](crate::cache::ArchiveCache)
+/// * `staging/{id}/` - archives are extracted here before being moved into the game
+/// * `staging/{id}.plan.json` - what installing the staged archive will do, see `plan_install`
+/// * `backup/{id}/` - the previous version of a mod while it's being updated
+/// * `disabled/{id}/` - disabled mods, moved out of the game directory
+/// * `overridden/{id}/` - files of a mod that lose a conflict to a higher priority mod
+/// * `replaced/{id}/` - files that were in the game before mod `id` overwrote them
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageLayout {
+    root: PathBuf,
+}
+
+impl StorageLayout {
+    pub fn new(download_path: &Path, game: &str) -> Self {
+        StorageLayout {
+            root: download_path.join(game),
+        }
+    }
+
+    /// Gets the layout of `game` under the configured `download_path`
+    pub async fn load(game: &str) -> Result<Self, String> {
+        check_game_key(game)?;
+        let active_settings = load_settings().await?;
+        let download_path = active_settings
+            .download_path
+            .ok_or("Expected download path")?;
+
+        Ok(Self::new(&download_path, game))
+    }
+
+    pub fn archives_dir(&self) -> PathBuf {
+        self.root.join("archives")
+    }
+
+    /// Where file `file_id` of mod `id` is downloaded to, before we know what format it is.
+    ///
+    /// A new upload of the mod has a new file id, so an unfinished download of the old file is
+    /// never resumed with the bytes of the new one.
+    pub fn download_path(&self, id: u32, file_id: u32) -> PathBuf {
+        self.archives_dir().join(format!("{id}-{file_id}.download"))
+    }
+
+    /// Where the archive of mod `id` is moved once its format is known
+    pub fn archive_path(&self, id: u32, ext: &str) -> PathBuf {
+        self.archives_dir().join(format!("{id}.{ext}"))
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.join("cache")
+    }
+
+    pub fn staging_dir(&self, id: u32) -> PathBuf {
+        self.root.join("staging").join(id.to_string())
+    }
+
+    /// Where the [`InstallPlan`](crate::lib::plan::InstallPlan) for the staged archive of mod `id` is kept
+    pub fn plan_path(&self, id: u32) -> PathBuf {
+        self.root.join("staging").join(format!("{id}.plan.json"))
+    }
+
+    pub fn backup_dir(&self, id: u32) -> PathBuf {
+        self.root.join("backup").join(id.to_string())
+    }
+
+    pub fn disabled_dir(&self) -> PathBuf {
+        self.root.join("disabled")
+    }
+
+    /// Where the overridden files of mod `id` are kept, under the name of their root
+    pub fn overridden_dir(&self, id: u32) -> PathBuf {
+        self.root.join("overridden").join(id.to_string())
+    }
+
+    /// Where the files that mod `id` overwrote are kept, under their full path
+    pub fn replaced_dir(&self, id: u32) -> PathBuf {
+        self.root.join("replaced").join(id.to_string())
+    }
+}
+
+/// The `download_path` used when none is configured, in the platform's local data directory
+/// (e.g. `~/.local/share/void_mod_manager` or `%LOCALAPPDATA%\void_mod_manager`).
+pub fn default_download_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("void_mod_manager")
+}
@@ -0,0 +1,152 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use tokio::fs;
+
+use crate::{
+    lib::{copy_all_cross_device, priority::redeploy, storage::StorageLayout},
+    registry::{InstalledMod, ModRegistry},
+};
+
+/// Moves the directories of an installed mod out of the game and into `disabled_root`.
+///
+/// Nothing is deleted, the mod's directory (including any user files) is moved to
+/// `{disabled_root}/{id}` and recorded in `installed.disabled_path`. Any other roots go next to
+/// it, see [`InstalledMod::disabled_root_path`].
+pub async fn disable_mod_files(
+    installed: &mut InstalledMod,
+    disabled_root: &Path,
+) -> Result<(), String> {
+    if !installed.is_enabled() {
+        debug!("{} is already disabled", installed.name);
+        return Ok(());
+    }
+
+    let mut disabled = installed.clone();
+    disabled.disabled_path = Some(disabled_root.join(installed.id.to_string()));
+    let moves = root_moves(&disabled, false);
+
+    move_roots(&moves)
+        .await
+        .map_err(|e| format!("Failed to disable {}: {e}", installed.name))?;
+
+    info!(
+        "Disabled {}, moved to \"{}\"",
+        installed.name,
+        disabled_root.display()
+    );
+    installed.disabled_path = disabled.disabled_path;
+    Ok(())
+}
+
+/// Moves a disabled mod back to where it was installed
+pub async fn enable_mod_files(installed: &mut InstalledMod) -> Result<(), String> {
+    if installed.is_enabled() {
+        debug!("{} is already enabled", installed.name);
+        return Ok(());
+    }
+
+    move_roots(&root_moves(installed, true))
+        .await
+        .map_err(|e| format!("Failed to enable {}: {e}", installed.name))?;
+
+    info!("Enabled {}", installed.name);
+    installed.disabled_path = None;
+    Ok(())
+}
+
+/// Pairs up where every root of a disabled mod is installed and where it's stored while disabled,
+/// as `(from, to)`
+fn root_moves(disabled: &InstalledMod, enabling: bool) -> Vec<(PathBuf, PathBuf)> {
+    disabled
+        .roots()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, root)| {
+            let stored = disabled.disabled_root_path(index)?;
+            Some(if enabling {
+                (stored, root.install_dir)
+            } else {
+                (root.install_dir, stored)
+            })
+        })
+        .collect()
+}
+
+/// Moves every `(from, to)` pair, moving back the ones already done if one of them fails
+async fn move_roots(moves: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    // Check everything up front, so we rarely have to undo anything
+    for (_, to) in moves {
+        if to.exists() {
+            return Err(format!(
+                "\"{}\" already exists, refusing to overwrite it",
+                to.display()
+            ));
+        }
+    }
+
+    for (done, (from, to)) in moves.iter().enumerate() {
+        if let Err(e) = move_dir(from, to).await {
+            for (from, to) in moves[..done].iter().rev() {
+                if let Err(undo_error) = move_dir(to, from).await {
+                    warn!("Failed to move \"{}\" back: {undo_error}", to.display());
+                }
+            }
+            return Err(e.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Enables or disables the installed mod `id` of `game`, and records it in the registry.
+///
+/// Disabled mods are kept in [`StorageLayout::disabled_dir`], so enabling them again doesn't need a download.
+pub async fn set_mod_enabled(game: &str, id: u32, enabled: bool) -> Result<InstalledMod, String> {
+    let mut registry = ModRegistry::lock(game).await?;
+    let installed = registry
+        .mods
+        .get_mut(&id)
+        .ok_or(format!("Mod {id} is not installed"))?;
+
+    if enabled {
+        enable_mod_files(installed).await?;
+    } else {
+        let disabled_root = StorageLayout::load(game).await?.disabled_dir();
+        disable_mod_files(installed, &disabled_root).await?;
+    }
+
+    registry.save()?;
+
+    // Whatever the mod was winning or losing has a new winner now
+    if let Err(e) = redeploy(game, &mut registry).await {
+        warn!("Failed to deploy the conflict priorities: {e}");
+    }
+
+    registry
+        .get(id)
+        .cloned()
+        .ok_or(format!("Mod {id} is not installed"))
+}
+
+/// Moves a directory (or a single file), falling back to copying when the source and destination are on different devices
+pub async fn move_dir(source: &Path, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    if fs::rename(source, destination).await.is_ok() {
+        return Ok(());
+    }
+
+    info!("Cross-device move detected, copying directory...");
+    copy_all_cross_device(source, destination).await?;
+    if source.is_dir() {
+        fs::remove_dir_all(source).await
+    } else {
+        fs::remove_file(source).await
+    }
+}
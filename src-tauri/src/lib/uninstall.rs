@@ -0,0 +1,243 @@
+use std::{collections::HashSet, io, path::PathBuf};
+
+use log::{debug, info, warn};
+use tokio::fs;
+
+use crate::{
+    lib::{error::InstallError, events::TaskEmitter, priority::redeploy, storage::StorageLayout},
+    registry::{InstalledMod, ModRegistry},
+};
+
+/// Removes every file and directory recorded in the install manifest of `installed`.
+///
+/// Only paths inside the mod's roots are touched, and directories are only removed
+/// once they are empty, so anything the user added after installing is left alone.
+/// Files the mod overwrote get their previous contents back instead of being removed.
+///
+/// # Arguments
+///
+/// * `installed` - The registry entry of the mod to remove.
+/// * `on_progress` - Called with `(removed, total)` after each manifest entry is handled.
+///
+/// # Returns
+///
+/// The number of paths that were actually removed.
+pub async fn remove_mod_files(
+    installed: &InstalledMod,
+    mut on_progress: impl FnMut(usize, usize),
+) -> io::Result<usize> {
+    let total = installed.files.len();
+    let mut removed = 0;
+    let mut handled = 0;
+
+    let (mut dirs, files): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        installed.files.iter().partition(|path| path.is_dir());
+
+    for file in files {
+        handled += 1;
+        if !installed.owns(file) {
+            warn!(
+                "Refusing to remove \"{}\", it is outside of the mod's directories",
+                file.display()
+            );
+        } else if let Some(overwritten) = installed.overwrote.iter().find(|o| &o.path == file) {
+            if overwritten.backup.is_file() {
+                debug!("Restoring overwritten file \"{}\"", file.display());
+                fs::copy(&overwritten.backup, file).await?;
+                fs::remove_file(&overwritten.backup).await?;
+            } else {
+                warn!(
+                    "The backup of \"{}\" is missing, removing it instead",
+                    file.display()
+                );
+                fs::remove_file(file).await?;
+                removed += 1;
+            }
+        } else if file.exists() {
+            debug!("Removing file \"{}\"", file.display());
+            fs::remove_file(file).await?;
+            removed += 1;
+        }
+        on_progress(handled, total);
+    }
+
+    // Deepest directories first, so parents are empty by the time we get to them
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in dirs {
+        handled += 1;
+        if !installed.owns(dir) {
+            warn!(
+                "Refusing to remove \"{}\", it is outside of the mod's directories",
+                dir.display()
+            );
+        } else {
+            match fs::remove_dir(dir).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Leaving directory \"{}\" in place: {e}", dir.display()),
+            }
+        }
+        on_progress(handled, total);
+    }
+
+    Ok(removed)
+}
+
+/// Hands the files of `installed` that a later mod overwrote over to that mod, so uninstalling
+/// `installed` doesn't take them out of the game.
+///
+/// The later mod's backup of such a file holds the copy of `installed`, it's swapped for what
+/// `installed` overwrote itself, or dropped if the file wasn't there before `installed`. The
+/// directories holding the files go along, so they're removed with the later mod.
+///
+/// # Returns
+///
+/// The files and directories that were handed over, they have to stay where they are.
+pub async fn hand_over_files(
+    registry: &mut ModRegistry,
+    installed: &InstalledMod,
+) -> io::Result<HashSet<PathBuf>> {
+    let mut kept = HashSet::new();
+
+    for file in &installed.files {
+        // The first mod to overwrite it after `installed` is the one that backed up its copy
+        let heir = registry
+            .mods
+            .values()
+            .filter(|other| other.id != installed.id && other.is_enabled())
+            .filter_map(|other| {
+                let index = other.overwrote.iter().position(|o| &o.path == file)?;
+                Some((other.installed_at, other.id, index))
+            })
+            .min();
+        let Some((_, heir_id, index)) = heir else {
+            continue;
+        };
+        let Some(heir) = registry.mods.get_mut(&heir_id) else {
+            continue;
+        };
+
+        let backup = &heir.overwrote[index].backup;
+        match installed.overwrote.iter().find(|o| &o.path == file) {
+            Some(original) if original.backup.is_file() => {
+                fs::copy(&original.backup, backup).await?;
+            }
+            _ => {
+                if backup.exists() {
+                    fs::remove_file(backup).await?;
+                }
+                heir.overwrote.remove(index);
+            }
+        }
+
+        debug!("Handing \"{}\" over to {}", file.display(), heir.name);
+        for dir in installed.files.iter().filter(|dir| file.starts_with(dir)) {
+            if dir != file && heir.owns(dir) && !heir.files.contains(dir) {
+                heir.files.push(dir.clone());
+            }
+            kept.insert(dir.clone());
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Disabled mods live in the manager's own store, so the whole directory of every root can go.
+/// The files the mod overwrote went with it when it was disabled, they're put back in the game.
+async fn remove_disabled_roots(installed: &InstalledMod) -> io::Result<usize> {
+    let mut removed = 0;
+    for index in 0..installed.roots().len() {
+        let Some(path) = installed.disabled_root_path(index) else {
+            continue;
+        };
+        if path.is_dir() {
+            fs::remove_dir_all(&path).await?;
+            removed += 1;
+        } else if path.exists() {
+            fs::remove_file(&path).await?;
+            removed += 1;
+        }
+    }
+
+    for overwritten in &installed.overwrote {
+        if !overwritten.backup.is_file() {
+            continue;
+        }
+        debug!(
+            "Restoring overwritten file \"{}\"",
+            overwritten.path.display()
+        );
+        if let Some(parent) = overwritten.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::copy(&overwritten.backup, &overwritten.path).await?;
+        fs::remove_file(&overwritten.backup).await?;
+    }
+
+    Ok(removed)
+}
+
+/// Removes the files of an enabled mod, except for the ones other mods took over
+async fn remove_enabled(
+    registry: &mut ModRegistry,
+    installed: &InstalledMod,
+    task: &TaskEmitter,
+) -> io::Result<usize> {
+    let kept = hand_over_files(registry, installed).await?;
+    if !kept.is_empty() {
+        // The mods that took files over have to know about them, even if the removal fails
+        registry.save().map_err(io::Error::other)?;
+    }
+
+    let mut leaving = installed.clone();
+    leaving.files.retain(|file| !kept.contains(file));
+    leaving.overwrote.retain(|o| !kept.contains(&o.path));
+    remove_mod_files(&leaving, |removed, total| task.removing(removed, total)).await
+}
+
+/// Uninstalls the mod `id` of `game` using its install manifest, and removes it from the registry.
+///
+/// Runs as a [`TaskKind::Uninstall`](crate::lib::events::TaskKind::Uninstall) task in the queue,
+/// reporting its progress through `task`.
+pub async fn uninstall_mod(game: &str, id: u32, task: &TaskEmitter) -> Result<(), InstallError> {
+    let mut registry = ModRegistry::lock(game)
+        .await
+        .map_err(InstallError::Registry)?;
+    let installed = registry
+        .get(id)
+        .cloned()
+        .ok_or(InstallError::NotInstalled)?;
+
+    let removed = if installed.is_enabled() {
+        remove_enabled(&mut registry, &installed, task).await
+    } else {
+        remove_disabled_roots(&installed).await
+    }
+    .map_err(InstallError::io("uninstall the mod"))?;
+
+    // The copies that lost a conflict go with it, and so do the backups it restored
+    let storage = StorageLayout::load(game)
+        .await
+        .map_err(InstallError::Settings)?;
+    for dir in [storage.overridden_dir(id), storage.replaced_dir(id)] {
+        if dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&dir).await {
+                warn!(
+                    "Failed to remove \"{}\" of {}: {e}",
+                    dir.display(),
+                    installed.name
+                );
+            }
+        }
+    }
+
+    registry.remove(id);
+    registry.save().map_err(InstallError::Registry)?;
+
+    // Files it was winning go back to the mod next in line
+    if let Err(e) = redeploy(game, &mut registry).await {
+        warn!("Failed to deploy the conflict priorities: {e}");
+    }
+
+    info!("Uninstalled {} ({removed} paths removed)", installed.name);
+    Ok(())
+}
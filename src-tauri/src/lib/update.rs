@@ -0,0 +1,280 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, info, warn};
+use tokio::fs;
+
+use crate::{
+    lib::{
+        copy_all_cross_device, copy_all_into, install_staged_mod,
+        layout::{walk_files, InstallLayout, PlannedRoot},
+        storage::StorageLayout,
+        uninstall::remove_mod_files,
+        ExtractedMod, Written,
+    },
+    registry::{InstalledMod, ModRelease},
+};
+
+/// Finds the files inside an installed mod that belong to the user rather than the mod,
+/// these are kept when the mod is updated.
+///
+/// A file is a user file if neither the installed version nor the new release ships it (e.g. BLT
+/// mods saving their settings next to `mod.txt`), or if it lives in a `saves` directory. Files
+/// either version ships are replaced by the new version even if they were edited, so the update
+/// always brings the new code.
+///
+/// # Arguments
+///
+/// * `installed` - The registry entry of the installed version.
+/// * `root` - One of the installed roots.
+/// * `new_root` - The staged root of the new release that replaces it, `None` if it has none.
+///
+/// # Returns
+///
+/// The user files in `root`, relative to it.
+pub fn find_user_files(
+    installed: &InstalledMod,
+    root: &Path,
+    new_root: Option<&Path>,
+) -> Vec<PathBuf> {
+    let shipped: HashSet<&PathBuf> = installed.files.iter().collect();
+    let mut user_files = Vec::new();
+
+    for file in walk_files(root) {
+        let Ok(relative) = file.strip_prefix(root) else {
+            continue;
+        };
+
+        let in_saves = relative
+            .components()
+            .any(|c| c.as_os_str().eq_ignore_ascii_case("saves"));
+        let ships = shipped.contains(&file)
+            || new_root.is_some_and(|new_root| new_root.join(relative).is_file());
+
+        if !ships || in_saves {
+            debug!("Keeping user file \"{}\"", relative.display());
+            user_files.push(relative.to_path_buf());
+        }
+    }
+
+    user_files
+}
+
+/// Replaces an installed mod with the new version staged in `mod_dir`, keeping its user files.
+///
+/// The installed roots are copied to a backup directory first, if anything goes wrong while
+/// swapping the files the backup is copied back so the previous version keeps working.
+///
+/// User files are put back into the new root installed to the same directory, or into the only
+/// new root if the mod had a single root that moved.
+///
+/// # Arguments
+///
+/// * `installed` - The registry entry of the currently installed version.
+/// * `mod_dir` - The staging directory the new version was extracted to.
+/// * `layout` - Where the files of the new version go, planned from `mod_dir`.
+/// * `storage` - Where the backup of the installed version, and the files it overwrote, are kept.
+///
+/// # Returns
+///
+/// What was extracted for the new version, to be recorded in the registry.
+pub async fn replace_installed_mod(
+    installed: &InstalledMod,
+    mod_dir: &Path,
+    layout: InstallLayout,
+    storage: &StorageLayout,
+) -> Result<ExtractedMod, String> {
+    let backup_dir = storage.backup_dir(installed.id);
+    let replaced_dir = storage.replaced_dir(installed.id);
+    if backup_dir.exists() {
+        fs::remove_dir_all(&backup_dir)
+            .await
+            .map_err(|e| format!("Failed to clear old backup: {e}"))?;
+    }
+
+    // Each root is backed up to `{backup_dir}/{index}`
+    let roots: Vec<(PathBuf, PathBuf)> = installed
+        .roots()
+        .into_iter()
+        .enumerate()
+        .map(|(index, root)| (root.install_dir, backup_dir.join(index.to_string())))
+        .collect();
+
+    // The root of the new release that takes over each installed root
+    let new_roots: Vec<Option<&PlannedRoot>> = roots
+        .iter()
+        .map(|(install_dir, _)| {
+            layout
+                .roots
+                .iter()
+                .find(|root| &root.install_dir == install_dir)
+                .or(match (roots.len(), layout.roots.as_slice()) {
+                    (1, [only]) => Some(only),
+                    _ => None,
+                })
+        })
+        .collect();
+    let targets: Vec<Option<PathBuf>> = new_roots
+        .iter()
+        .map(|new_root| new_root.map(|root| root.install_dir.clone()))
+        .collect();
+
+    let user_files: Vec<Vec<PathBuf>> = roots
+        .iter()
+        .zip(&new_roots)
+        .map(|((install_dir, _), new_root)| {
+            let staged = new_root.map(|root| mod_dir.join(&root.source));
+            find_user_files(installed, install_dir, staged.as_deref())
+        })
+        .collect();
+    info!(
+        "Updating {}, keeping {} user files",
+        installed.name,
+        user_files.iter().map(Vec::len).sum::<usize>()
+    );
+
+    // Snapshot the installed version so we can roll back
+    for (install_dir, backup) in &roots {
+        copy_all_cross_device(install_dir, backup)
+            .await
+            .map_err(|e| format!("Failed to back up the installed mod: {e}"))?;
+    }
+
+    if let Err(e) = remove_mod_files(installed, |_, _| {}).await {
+        return Err(rollback(
+            installed,
+            &backup_dir,
+            &replaced_dir,
+            &roots,
+            None,
+            format!("Failed to remove old files: {e}"),
+        )
+        .await);
+    }
+
+    // A failed install removes what it copied itself
+    let extracted = match install_staged_mod(mod_dir, layout, installed.id, &replaced_dir).await {
+        Ok(extracted) => extracted,
+        Err(e) => {
+            return Err(rollback(
+                installed,
+                &backup_dir,
+                &replaced_dir,
+                &roots,
+                None,
+                e.to_string(),
+            )
+            .await)
+        }
+    };
+
+    for (((install_dir, backup), user_files), target) in roots.iter().zip(&user_files).zip(targets)
+    {
+        if user_files.is_empty() {
+            continue;
+        }
+
+        let Some(target) = target else {
+            warn!(
+                "\"{}\" isn't part of the new version, dropping its user files",
+                install_dir.display()
+            );
+            continue;
+        };
+
+        if let Err(e) = restore_user_files(backup, &target, user_files).await {
+            return Err(rollback(
+                installed,
+                &backup_dir,
+                &replaced_dir,
+                &roots,
+                Some(extracted),
+                format!("Failed to restore user files: {e}"),
+            )
+            .await);
+        }
+
+        if target != *install_dir {
+            warn!(
+                "Mod moved from \"{}\" to \"{}\", user files were copied over",
+                install_dir.display(),
+                target.display()
+            );
+        }
+    }
+
+    let _ = fs::remove_dir_all(&backup_dir).await;
+    Ok(extracted)
+}
+
+/// Removes the files of the new version (if it got installed), then copies the backup of every
+/// root of `installed` back in place, and returns the error that caused the rollback.
+///
+/// Files the old version overwrote are backed up to `replaced_dir` again on the way, so its
+/// registry entry still restores them.
+async fn rollback(
+    installed: &InstalledMod,
+    backup_dir: &Path,
+    replaced_dir: &Path,
+    roots: &[(PathBuf, PathBuf)],
+    new_version: Option<ExtractedMod>,
+    reason: String,
+) -> String {
+    error!(
+        "Update of {} failed, rolling back: {reason}",
+        installed.name
+    );
+
+    // Otherwise the rolled back mod would be a mix of both versions
+    let new_version = new_version.and_then(|extracted| {
+        InstalledMod::from_extracted(
+            installed.id,
+            installed.name.clone(),
+            ModRelease::default(),
+            extracted,
+        )
+    });
+    if let Some(new_version) = new_version {
+        if let Err(e) = remove_mod_files(&new_version, |_, _| {}).await {
+            error!(
+                "Rollback failed, the backup is kept at \"{}\"",
+                backup_dir.display()
+            );
+            return format!("{reason} (removing the new version failed: {e})");
+        }
+    }
+
+    for (install_dir, backup) in roots {
+        let mut written = Written::default();
+        if let Err(e) = copy_all_into(backup, install_dir, Some(replaced_dir), &mut written).await {
+            error!(
+                "Rollback failed, the backup is kept at \"{}\"",
+                backup_dir.display()
+            );
+            return format!("{reason} (rollback failed: {e})");
+        }
+    }
+
+    let _ = fs::remove_dir_all(backup_dir).await;
+    info!("Rolled back {}", installed.name);
+    reason
+}
+
+async fn restore_user_files(
+    backup_dir: &Path,
+    install_dir: &Path,
+    user_files: &[PathBuf],
+) -> io::Result<()> {
+    for relative in user_files {
+        let destination = install_dir.join(relative);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::copy(backup_dir.join(relative), &destination).await?;
+    }
+
+    Ok(())
+}
@@ -1,12 +1,32 @@
 // use game_api::{download_mod_from_id, get_mods};
-use games::{payday2::Payday2API, GameModAPI, Mod, ModWithMeta};
+use cache::{get_cache_info, prune_cache};
+use conflicts::get_conflicts;
+use games::{
+    payday2::{self, Payday2API},
+    GameModAPI, Mod, ModUpdate, ModWithMeta,
+};
+use lib::{
+    events::{TaskEmitter, TaskKind},
+    plan::InstallPlan,
+};
 use log::{debug, error, info, trace, warn};
+use profiles::{
+    delete_profile, get_active_profile, get_profiles, save_profile, set_mod_priority,
+    switch_profile,
+};
+use queue::{cancel_task, clear_finished_tasks, list_tasks, pause_task, resume_task, TaskQueue};
+use registry::{get_installed_mod, get_installed_mods, InstalledMod};
 use settings::{load_settings, save_settings};
-use tauri::Window;
+use tauri::{State, Window};
 
+mod cache;
+mod conflicts;
 mod games;
 mod lib;
 mod mod_manager;
+mod profiles;
+mod queue;
+mod registry;
 mod settings;
 mod test;
 
@@ -23,9 +43,90 @@ async fn get_mods(query: Option<String>) -> Vec<ModWithMeta> {
     return Payday2API.fetch_mods(query).await;
 }
 
+/// Queues the mod for download, returning the id of the task
 #[tauri::command]
-async fn download_mod_from_id(id: u32, window: Window) -> Result<(), String> {
-    return Payday2API.impl_download_mod_from_id(id, window).await;
+async fn download_mod_from_id(
+    id: u32,
+    window: Window,
+    queue: State<'_, TaskQueue>,
+) -> Result<u64, String> {
+    return queue.enqueue(window, id, TaskKind::Install).await;
+}
+
+#[tauri::command]
+async fn check_for_updates() -> Result<Vec<ModUpdate>, String> {
+    Payday2API.impl_check_for_updates().await
+}
+
+/// Queues the update of an installed mod, returning the id of the task
+#[tauri::command]
+async fn update_mod(id: u32, window: Window, queue: State<'_, TaskQueue>) -> Result<u64, String> {
+    queue.enqueue(window, id, TaskKind::Update).await
+}
+
+/// Downloads the archive of mod `id` and returns what installing it would do, so the user can
+/// confirm it. Installing the mod afterwards executes this plan.
+#[tauri::command]
+async fn plan_install(
+    id: u32,
+    window: Window,
+    queue: State<'_, TaskQueue>,
+) -> Result<InstallPlan, String> {
+    let task = TaskEmitter::new(window, id, TaskKind::Plan);
+    let _claim = queue.claim(&task).map_err(|e| e.to_string())?;
+    task.started();
+
+    let result = Payday2API.plan_install(id, &task).await;
+    match &result {
+        Ok(_) => task.done(),
+        Err(e) => task.failed(e),
+    }
+    result.map_err(|e| e.to_string())
+}
+
+/// Plans mod `id` and the mods it needs that aren't installed yet, returning the order to
+/// install them in for the user to confirm
+#[tauri::command]
+async fn plan_dependencies(
+    id: u32,
+    window: Window,
+    queue: State<'_, TaskQueue>,
+) -> Result<Vec<u32>, String> {
+    Payday2API
+        .plan_dependencies(id, &window, &queue)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Queues the install of every mod in `ids`, each one starting once the one before it is done,
+/// returning the ids of the tasks
+#[tauri::command]
+async fn install_in_order(
+    ids: Vec<u32>,
+    window: Window,
+    queue: State<'_, TaskQueue>,
+) -> Result<Vec<u64>, String> {
+    queue.enqueue_chain(window, &ids, TaskKind::Install).await
+}
+
+/// Queues the uninstall of an installed mod, returning the id of the task
+#[tauri::command]
+async fn uninstall_mod(
+    id: u32,
+    window: Window,
+    queue: State<'_, TaskQueue>,
+) -> Result<u64, String> {
+    queue.enqueue(window, id, TaskKind::Uninstall).await
+}
+
+#[tauri::command]
+async fn enable_mod(id: u32) -> Result<InstalledMod, String> {
+    lib::toggle::set_mod_enabled(payday2::GAME_KEY, id, true).await
+}
+
+#[tauri::command]
+async fn disable_mod(id: u32) -> Result<InstalledMod, String> {
+    lib::toggle::set_mod_enabled(payday2::GAME_KEY, id, false).await
 }
 
 #[tauri::command]
@@ -44,6 +145,7 @@ fn main() {
     error!("Oh, shit");
 
     tauri::Builder::default()
+        .manage(TaskQueue::default())
         .invoke_handler(tauri::generate_handler![
             // Settings
             save_settings,
@@ -51,8 +153,36 @@ fn main() {
             // Game API
             get_mods,
             download_mod_from_id,
+            uninstall_mod,
+            enable_mod,
+            disable_mod,
+            check_for_updates,
+            update_mod,
+            plan_install,
+            plan_dependencies,
+            install_in_order,
+            get_conflicts,
             // Core
             get_steam_games,
+            // Installed mods
+            get_installed_mods,
+            get_installed_mod,
+            // Profiles
+            get_profiles,
+            save_profile,
+            delete_profile,
+            get_active_profile,
+            switch_profile,
+            set_mod_priority,
+            // Task queue
+            list_tasks,
+            cancel_task,
+            pause_task,
+            resume_task,
+            clear_finished_tasks,
+            // Archive cache
+            get_cache_info,
+            prune_cache,
         ]) // Settings commands
         // .invoke_handler(tauri::generate_handler![download_mod_from_id])
         .run(tauri::generate_context!())
@@ -0,0 +1,347 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    games::{
+        check_game_key,
+        payday2::{self, Payday2API},
+    },
+    lib::{
+        priority::{deploy, redeploy},
+        storage::StorageLayout,
+        toggle::{disable_mod_files, enable_mod_files},
+        uninstall::remove_mod_files,
+    },
+    registry::ModRegistry,
+    settings::{load_settings, save_settings},
+};
+
+/// A named set of installed mods that should be enabled together
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// The ModWorkshop ids of the mods that are enabled in this profile
+    pub mods: Vec<u32>,
+    /// Which mod wins when mods override the same asset, highest priority first. Mods that
+    /// aren't listed lose to the ones that are, see [`deploy`].
+    #[serde(default)]
+    pub priority: Vec<u32>,
+}
+
+/// The profiles of a single game.
+///
+/// This is saved to `{config_dir}/void_mod_manager/profiles/{game}.json`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProfileStore {
+    #[serde(skip)]
+    path: PathBuf,
+    pub profiles: Vec<Profile>,
+}
+
+impl ProfileStore {
+    pub fn load(game: &str) -> Result<Self, String> {
+        Self::load_from(Self::profiles_file_path(game)?)
+    }
+
+    pub fn load_from(path: PathBuf) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(ProfileStore {
+                path,
+                profiles: Vec::new(),
+            });
+        }
+
+        let data = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut store: ProfileStore = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+        store.path = path;
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        info!("Saving profiles...");
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, data).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Adds a profile, replacing any existing profile with the same name
+    pub fn upsert(&mut self, profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Profile> {
+        let index = self.profiles.iter().position(|p| p.name == name)?;
+        Some(self.profiles.remove(index))
+    }
+
+    fn profiles_file_path(game: &str) -> Result<PathBuf, String> {
+        check_game_key(game)?;
+        let mut path = dirs::config_dir().ok_or("Failed to find the config directory")?;
+        path.push("void_mod_manager");
+        path.push("profiles");
+        path.push(format!("{game}.json"));
+        Ok(path)
+    }
+}
+
+/// The mods that have to be moved to go from the current state of `registry` to `profile`
+#[derive(Debug, Default, PartialEq)]
+pub struct ProfileSwitch {
+    pub enable: Vec<u32>,
+    pub disable: Vec<u32>,
+}
+
+/// Works out which installed mods need to be enabled or disabled to match `profile`.
+///
+/// Mods in the profile that aren't installed are ignored, [`apply_profile`] installs them from
+/// the archive cache first if it can.
+pub fn plan_switch(registry: &ModRegistry, profile: &Profile) -> ProfileSwitch {
+    let mut switch = ProfileSwitch::default();
+
+    for (id, installed) in &registry.mods {
+        let wanted = profile.mods.contains(id);
+        if wanted && !installed.is_enabled() {
+            switch.enable.push(*id);
+        } else if !wanted && installed.is_enabled() {
+            switch.disable.push(*id);
+        }
+    }
+
+    for id in &profile.mods {
+        if !registry.mods.contains_key(id) {
+            warn!(
+                "Profile {} wants mod {id}, but it isn't installed",
+                profile.name
+            );
+        }
+    }
+
+    switch
+}
+
+/// Enables and disables mods so that exactly the mods in `profile` are active.
+///
+/// Mods in the profile that aren't installed are installed from the archive cache, without
+/// going online. Mods that aren't cached are skipped.
+///
+/// If moving any mod fails, every mod that was already moved is moved back (and the mods
+/// installed from the cache are removed again), so the game is never left half way between
+/// two profiles.
+///
+/// The conflict priorities of `profile` are deployed once every mod is in place, it doesn't
+/// need to be the active profile yet.
+pub async fn apply_profile(game: &str, profile: &Profile) -> Result<(), String> {
+    let mut registry = ModRegistry::lock(game).await?;
+    let missing: Vec<u32> = profile
+        .mods
+        .iter()
+        .copied()
+        .filter(|id| registry.get(*id).is_none())
+        .collect();
+
+    let mut from_cache = Vec::new();
+    for id in missing {
+        match Payday2API.install_from_cache(id).await {
+            Ok(Some(installed)) => {
+                registry.insert(installed);
+                from_cache.push(id);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to install mod {id} from the cache: {e}"),
+        }
+    }
+
+    let storage = StorageLayout::load(game).await?;
+    let disabled_root = storage.disabled_dir();
+    let switch = plan_switch(&registry, profile);
+    debug!("Switching to profile {}: {:?}", profile.name, switch);
+
+    // The mods we have moved so far, and whether they were enabled (true) or disabled (false)
+    let mut moved: Vec<(u32, bool)> = Vec::new();
+    let mut failure = None;
+
+    for (id, enable) in switch
+        .disable
+        .iter()
+        .map(|id| (*id, false))
+        .chain(switch.enable.iter().map(|id| (*id, true)))
+    {
+        let Some(installed) = registry.mods.get_mut(&id) else {
+            continue;
+        };
+
+        let result = if enable {
+            enable_mod_files(installed).await
+        } else {
+            disable_mod_files(installed, &disabled_root).await
+        };
+
+        match result {
+            Ok(()) => moved.push((id, enable)),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    if let Some(e) = failure {
+        error!(
+            "Failed to switch to profile {}, rolling back: {e}",
+            profile.name
+        );
+        for (id, enabled) in moved.into_iter().rev() {
+            let Some(installed) = registry.mods.get_mut(&id) else {
+                continue;
+            };
+
+            let undo = if enabled {
+                disable_mod_files(installed, &disabled_root).await
+            } else {
+                enable_mod_files(installed).await
+            };
+
+            if let Err(undo_error) = undo {
+                error!("Failed to roll back mod {id}: {undo_error}");
+            }
+        }
+
+        for id in from_cache {
+            let Some(installed) = registry.remove(id) else {
+                continue;
+            };
+
+            if let Err(undo_error) = remove_mod_files(&installed, |_, _| {}).await {
+                error!("Failed to remove mod {id} again: {undo_error}");
+            }
+        }
+
+        registry.save()?;
+        return Err(e);
+    }
+
+    if let Err(e) = deploy(&mut registry, &profile.priority, &storage).await {
+        warn!(
+            "Failed to deploy the conflict priorities of {}: {e}",
+            profile.name
+        );
+    }
+
+    registry.save()?;
+    Ok(())
+}
+
+/// Whether `name` is the active profile of `game`
+async fn is_active_profile(game: &str, name: &str) -> Result<bool, String> {
+    let settings = load_settings().await?;
+    Ok(settings
+        .active_profiles
+        .is_some_and(|active| active.get(game).is_some_and(|n| n == name)))
+}
+
+/// Re-deploys the conflicting files if `name` is the active profile of `game`
+async fn redeploy_if_active(game: &str, name: &str) -> Result<(), String> {
+    if is_active_profile(game, name).await? {
+        let mut registry = ModRegistry::lock(game).await?;
+        redeploy(game, &mut registry).await?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_profiles(game: Option<String>) -> Result<Vec<Profile>, String> {
+    Ok(ProfileStore::load(game.as_deref().unwrap_or(payday2::GAME_KEY))?.profiles)
+}
+
+#[tauri::command]
+pub async fn save_profile(profile: Profile, game: Option<String>) -> Result<(), String> {
+    let game = game.as_deref().unwrap_or(payday2::GAME_KEY);
+    let mut store = ProfileStore::load(game)?;
+    let name = profile.name.clone();
+    store.upsert(profile);
+    store.save()?;
+
+    redeploy_if_active(game, &name).await
+}
+
+/// Sets which mods win conflicts in profile `name`, highest priority first. If it's the active
+/// profile, the files whose winner changed are swapped right away.
+#[tauri::command]
+pub async fn set_mod_priority(
+    name: String,
+    priority: Vec<u32>,
+    game: Option<String>,
+) -> Result<(), String> {
+    let game = game.as_deref().unwrap_or(payday2::GAME_KEY);
+    let mut store = ProfileStore::load(game)?;
+    let profile = store
+        .profiles
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or(format!("No profile named {name}"))?;
+
+    info!(
+        "Setting the mod priority of profile {name} to {:?}",
+        priority
+    );
+    profile.priority = priority;
+    store.save()?;
+
+    redeploy_if_active(game, &name).await
+}
+
+#[tauri::command]
+pub async fn delete_profile(name: String, game: Option<String>) -> Result<(), String> {
+    let game = game.as_deref().unwrap_or(payday2::GAME_KEY);
+    let mut store = ProfileStore::load(game)?;
+    store.remove(&name);
+    store.save()?;
+
+    // Forget the active profile if we just deleted it
+    let mut settings = load_settings().await?;
+    if let Some(active) = settings.active_profiles.as_mut() {
+        if active.get(game) == Some(&name) {
+            active.remove(game);
+            save_settings(settings).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_active_profile(game: Option<String>) -> Result<Option<String>, String> {
+    let game = game.as_deref().unwrap_or(payday2::GAME_KEY);
+    let settings = load_settings().await?;
+    Ok(settings
+        .active_profiles
+        .and_then(|active| active.get(game).cloned()))
+}
+
+#[tauri::command]
+pub async fn switch_profile(name: String, game: Option<String>) -> Result<(), String> {
+    let game = game.as_deref().unwrap_or(payday2::GAME_KEY);
+    let store = ProfileStore::load(game)?;
+    let profile = store.get(&name).ok_or(format!("No profile named {name}"))?;
+
+    info!("Switching to profile {name}");
+    apply_profile(game, profile).await?;
+
+    let mut settings = load_settings().await?;
+    settings
+        .active_profiles
+        .get_or_insert_with(BTreeMap::new)
+        .insert(game.to_string(), name);
+    save_settings(settings).await
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use futures::future::BoxFuture;
+use log::{debug, error, info};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{
+    games::{
+        payday2::{self, Payday2API},
+        GameModAPI,
+    },
+    lib::{
+        error::{InstallError, ModErrorPayload},
+        events::{TaskControl, TaskEmitter, TaskKind, TaskRef, TaskSignal},
+        uninstall::uninstall_mod,
+    },
+    settings::load_settings,
+};
+
+/// How many tasks run at once if the setting is missing
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+
+/// Does the actual work of a task, [`run_task`] unless a test says otherwise
+pub type TaskRunner =
+    Arc<dyn Fn(TaskEmitter) -> BoxFuture<'static, Result<(), InstallError>> + Send + Sync>;
+
+/// Where a task is in the queue
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Paused,
+    Cancelled,
+    Done,
+    Failed,
+}
+
+impl TaskStatus {
+    /// Whether the task can still run (or is running)
+    pub fn is_active(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Queued | TaskStatus::Running | TaskStatus::Paused
+        )
+    }
+}
+
+/// A task as returned by `list_tasks`
+#[derive(Serialize, Clone, Debug)]
+pub struct QueuedTask {
+    #[serde(flatten)]
+    pub task: TaskRef,
+    pub status: TaskStatus,
+    /// Why the task failed, if it did
+    pub error: Option<ModErrorPayload>,
+}
+
+struct QueueEntry {
+    info: QueuedTask,
+    emitter: TaskEmitter,
+    /// The task that has to be done before this one can start
+    after: Option<u64>,
+    /// Used to pause or cancel the task while it's running
+    signal: Option<watch::Sender<TaskSignal>>,
+}
+
+struct QueueState {
+    tasks: BTreeMap<u64, QueueEntry>,
+    running: usize,
+    max_concurrent: usize,
+}
+
+/// Runs install, update and uninstall tasks in the order they were queued, at most
+/// `max_concurrent_downloads` (from the settings) at a time. A mod only ever has one task
+/// that isn't finished, including plans that run outside the queue (see [`claim`](Self::claim)).
+///
+/// Tasks can be paused, resumed and cancelled. Only the download can be interrupted, a task
+/// that's already extracting the mod finishes first.
+#[derive(Clone)]
+pub struct TaskQueue {
+    state: Arc<Mutex<QueueState>>,
+    runner: TaskRunner,
+    /// Whether `max_concurrent` follows the settings
+    configurable: bool,
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        TaskQueue {
+            configurable: true,
+            ..Self::with_runner(DEFAULT_MAX_CONCURRENT, Arc::new(run_task))
+        }
+    }
+}
+
+/// A task registered with [`TaskQueue::claim`], it's forgotten once this is dropped
+pub struct TaskClaim {
+    queue: TaskQueue,
+    task_id: u64,
+}
+
+impl Drop for TaskClaim {
+    fn drop(&mut self) {
+        self.queue.lock().tasks.remove(&self.task_id);
+    }
+}
+
+impl TaskQueue {
+    /// A queue that runs its tasks with `runner`, `max_concurrent` at a time whatever the
+    /// settings say
+    pub fn with_runner(max_concurrent: usize, runner: TaskRunner) -> Self {
+        TaskQueue {
+            state: Arc::new(Mutex::new(QueueState {
+                tasks: BTreeMap::new(),
+                running: 0,
+                max_concurrent,
+            })),
+            runner,
+            configurable: false,
+        }
+    }
+
+    /// Adds a task for mod `id` to the end of the queue, and starts it if there is a free slot.
+    ///
+    /// # Returns
+    ///
+    /// The id of the new task, which is also in all of its `mod_task` events.
+    pub async fn enqueue(
+        &self,
+        window: tauri::Window,
+        mod_id: u32,
+        kind: TaskKind,
+    ) -> Result<u64, String> {
+        let task_ids = self.enqueue_chain(window, &[mod_id], kind).await?;
+        Ok(task_ids[0])
+    }
+
+    /// Adds a task for each of `mod_ids` to the end of the queue, each one only starting once
+    /// the one before it is done. If a task doesn't finish, the ones after it fail.
+    ///
+    /// Either every task is queued or none are.
+    ///
+    /// # Returns
+    ///
+    /// The ids of the new tasks, in the same order as `mod_ids`.
+    pub async fn enqueue_chain(
+        &self,
+        window: tauri::Window,
+        mod_ids: &[u32],
+        kind: TaskKind,
+    ) -> Result<Vec<u64>, String> {
+        let tasks = mod_ids
+            .iter()
+            .map(|mod_id| TaskEmitter::new(window.clone(), *mod_id, kind))
+            .collect();
+        self.enqueue_tasks(tasks).await
+    }
+
+    /// Adds `tasks` to the end of the queue, each one only starting once the one before it is
+    /// done, see [`enqueue_chain`](Self::enqueue_chain)
+    pub async fn enqueue_tasks(&self, tasks: Vec<TaskEmitter>) -> Result<Vec<u64>, String> {
+        self.refresh_max_concurrent().await;
+
+        let mut task_ids = Vec::new();
+        {
+            let mut state = self.lock();
+            for (index, emitter) in tasks.iter().enumerate() {
+                let mod_id = emitter.task().mod_id;
+                Self::check_free(&state, mod_id).map_err(|e| e.to_string())?;
+                if tasks[..index].iter().any(|t| t.task().mod_id == mod_id) {
+                    return Err(format!("Mod {mod_id} is in the list twice"));
+                }
+            }
+
+            for emitter in tasks {
+                let task = emitter.task();
+                let after = task_ids.last().copied();
+
+                info!(
+                    "Queued {:?} of mod {} as task {}, after {:?}",
+                    task.kind, task.mod_id, task.task_id, after
+                );
+                emitter.queued();
+                state.tasks.insert(
+                    task.task_id,
+                    QueueEntry {
+                        info: QueuedTask {
+                            task,
+                            status: TaskStatus::Queued,
+                            error: None,
+                        },
+                        emitter,
+                        after,
+                        signal: None,
+                    },
+                );
+                task_ids.push(task.task_id);
+            }
+        }
+
+        self.pump();
+        Ok(task_ids)
+    }
+
+    /// Registers a task that runs outside the queue, because the front end waits for its result
+    /// (plans). It shows up as running, and no other task for its mod can be queued until the
+    /// returned claim is dropped.
+    pub fn claim(&self, emitter: &TaskEmitter) -> Result<TaskClaim, InstallError> {
+        let task = emitter.task();
+        let mut state = self.lock();
+        Self::check_free(&state, task.mod_id)?;
+
+        state.tasks.insert(
+            task.task_id,
+            QueueEntry {
+                info: QueuedTask {
+                    task,
+                    status: TaskStatus::Running,
+                    error: None,
+                },
+                emitter: emitter.clone(),
+                after: None,
+                signal: None,
+            },
+        );
+        Ok(TaskClaim {
+            queue: self.clone(),
+            task_id: task.task_id,
+        })
+    }
+
+    /// Fails if mod `mod_id` has a task that isn't finished, two tasks for the same mod would
+    /// fight over its files
+    fn check_free(state: &QueueState, mod_id: u32) -> Result<(), InstallError> {
+        let busy = state
+            .tasks
+            .values()
+            .any(|e| e.info.task.mod_id == mod_id && e.info.status.is_active());
+        match busy {
+            true => Err(InstallError::AlreadyQueued(mod_id)),
+            false => Ok(()),
+        }
+    }
+
+    /// Stops a task, keeping what it has downloaded so far so it can be resumed
+    pub fn pause(&self, task_id: u64) -> Result<(), String> {
+        let mut state = self.lock();
+        let entry = state
+            .tasks
+            .get_mut(&task_id)
+            .ok_or(format!("No task with id {task_id}"))?;
+
+        match entry.info.status {
+            // The task reports back once it has stopped, see `finished`
+            TaskStatus::Running => Self::signal(entry, TaskSignal::Pause),
+            TaskStatus::Queued => {
+                entry.info.status = TaskStatus::Paused;
+                entry.emitter.finish(&Err(InstallError::Paused));
+                Ok(())
+            }
+            status => Err(format!("Can't pause a task that is {status:?}")),
+        }
+    }
+
+    /// Puts a paused task back in the queue, its download picks up where it stopped
+    pub async fn resume(&self, task_id: u64) -> Result<(), String> {
+        self.refresh_max_concurrent().await;
+
+        {
+            let mut state = self.lock();
+            let entry = state
+                .tasks
+                .get_mut(&task_id)
+                .ok_or(format!("No task with id {task_id}"))?;
+
+            if entry.info.status != TaskStatus::Paused {
+                return Err(format!(
+                    "Can't resume a task that is {:?}",
+                    entry.info.status
+                ));
+            }
+
+            entry.info.status = TaskStatus::Queued;
+            entry.emitter.queued();
+        }
+
+        self.pump();
+        Ok(())
+    }
+
+    /// Stops a task for good and throws away its partial download
+    pub fn cancel(&self, task_id: u64) -> Result<(), String> {
+        let mut state = self.lock();
+        let entry = state
+            .tasks
+            .get_mut(&task_id)
+            .ok_or(format!("No task with id {task_id}"))?;
+
+        match entry.info.status {
+            TaskStatus::Running => Self::signal(entry, TaskSignal::Cancel),
+            TaskStatus::Queued | TaskStatus::Paused => {
+                entry.info.status = TaskStatus::Cancelled;
+                entry.emitter.finish(&Err(InstallError::Cancelled));
+                // The tasks waiting for it will never start
+                Self::fail_orphans(&mut state);
+                Ok(())
+            }
+            status => Err(format!("Can't cancel a task that is {status:?}")),
+        }
+    }
+
+    /// Every task the queue knows about, oldest first
+    pub fn list(&self) -> Vec<QueuedTask> {
+        self.lock().tasks.values().map(|e| e.info.clone()).collect()
+    }
+
+    /// Forgets the tasks that are done, failed or cancelled
+    pub fn clear_finished(&self) {
+        self.lock().tasks.retain(|_, e| e.info.status.is_active());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        // A panic while holding the lock can't leave the state half updated, so keep going
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn signal(entry: &QueueEntry, signal: TaskSignal) -> Result<(), String> {
+        debug!("Sending {:?} to task {}", signal, entry.info.task.task_id);
+        entry
+            .signal
+            .as_ref()
+            .ok_or("The task isn't running".to_string())?
+            .send(signal)
+            .map_err(|_| "The task already finished".to_string())
+    }
+
+    /// Picks up `max_concurrent_downloads` from the settings, so changes apply to the next task
+    async fn refresh_max_concurrent(&self) {
+        if !self.configurable {
+            return;
+        }
+
+        let max = load_settings()
+            .await
+            .ok()
+            .and_then(|s| s.max_concurrent_downloads)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT)
+            .max(1);
+        self.lock().max_concurrent = max;
+    }
+
+    /// Starts queued tasks until all slots are taken
+    fn pump(&self) {
+        let mut state = self.lock();
+        Self::fail_orphans(&mut state);
+
+        while state.running < state.max_concurrent {
+            // Tasks in a chain wait for the one before them
+            let ready = state
+                .tasks
+                .iter()
+                .find(|(_, e)| {
+                    e.info.status == TaskStatus::Queued
+                        && e.after.is_none_or(|after| {
+                            state
+                                .tasks
+                                .get(&after)
+                                .is_none_or(|a| a.info.status == TaskStatus::Done)
+                        })
+                })
+                .map(|(task_id, _)| *task_id);
+            let Some(entry) = ready.and_then(|task_id| state.tasks.get_mut(&task_id)) else {
+                break;
+            };
+
+            let (sender, control) = TaskControl::channel();
+            entry.signal = Some(sender);
+            entry.info.status = TaskStatus::Running;
+            let emitter = entry.emitter.clone().with_control(control);
+            state.running += 1;
+
+            let queue = self.clone();
+            let runner = self.runner.clone();
+            tokio::spawn(async move {
+                emitter.started();
+                // Run on its own, so a panic still ends the task and frees its slot
+                let work = emitter.clone();
+                let result = tokio::spawn(async move { runner(work).await });
+                let result = result.await.unwrap_or_else(|e| {
+                    error!("Task {} panicked: {e}", emitter.task().task_id);
+                    Err(InstallError::Panicked(e.to_string()))
+                });
+                emitter.finish(&result);
+                queue.finished(emitter.task().task_id, result);
+            });
+        }
+    }
+
+    /// Fails the tasks waiting for a task that failed or was cancelled, and the ones waiting
+    /// for those
+    fn fail_orphans(state: &mut QueueState) {
+        loop {
+            let orphans: Vec<(u64, u32)> = state
+                .tasks
+                .iter()
+                .filter(|(_, e)| e.info.status.is_active() && e.info.status != TaskStatus::Running)
+                .filter_map(|(task_id, e)| {
+                    let after = state.tasks.get(&e.after?)?;
+                    let given_up = matches!(
+                        after.info.status,
+                        TaskStatus::Failed | TaskStatus::Cancelled
+                    );
+                    given_up.then_some((*task_id, after.info.task.mod_id))
+                })
+                .collect();
+            if orphans.is_empty() {
+                return;
+            }
+
+            for (task_id, needed) in orphans {
+                let Some(entry) = state.tasks.get_mut(&task_id) else {
+                    continue;
+                };
+                let e = InstallError::DependencyFailed(needed);
+                debug!("Task {task_id} can't run: {e}");
+                entry.info.error = Some(e.payload(entry.info.task.mod_id));
+                entry.info.status = TaskStatus::Failed;
+                entry.emitter.finish(&Err(e));
+            }
+        }
+    }
+
+    /// Records how a task ended and starts the next one
+    fn finished(&self, task_id: u64, result: Result<(), InstallError>) {
+        {
+            let mut state = self.lock();
+            state.running -= 1;
+
+            if let Some(entry) = state.tasks.get_mut(&task_id) {
+                entry.signal = None;
+                entry.info.status = match &result {
+                    Ok(()) => TaskStatus::Done,
+                    Err(InstallError::Paused) => TaskStatus::Paused,
+                    Err(InstallError::Cancelled) => TaskStatus::Cancelled,
+                    Err(e) => {
+                        entry.info.error = Some(e.payload(entry.info.task.mod_id));
+                        TaskStatus::Failed
+                    }
+                };
+                debug!("Task {task_id} finished: {:?}", entry.info.status);
+            }
+        }
+
+        self.pump();
+    }
+}
+
+/// Does the actual work of a task
+fn run_task(task: TaskEmitter) -> BoxFuture<'static, Result<(), InstallError>> {
+    Box::pin(async move {
+        let TaskRef { mod_id, kind, .. } = task.task();
+        match kind {
+            TaskKind::Install => Payday2API.impl_download_mod_from_id(mod_id, &task).await,
+            TaskKind::Update => Payday2API.impl_update_mod(mod_id, &task).await,
+            TaskKind::Uninstall => uninstall_mod(payday2::GAME_KEY, mod_id, &task).await,
+            // Plans are waited on by the front end, so they are never queued (see `claim`)
+            TaskKind::Plan => Ok(()),
+        }
+    })
+}
+
+#[tauri::command]
+pub async fn list_tasks(queue: tauri::State<'_, TaskQueue>) -> Result<Vec<QueuedTask>, String> {
+    Ok(queue.list())
+}
+
+#[tauri::command]
+pub async fn cancel_task(task_id: u64, queue: tauri::State<'_, TaskQueue>) -> Result<(), String> {
+    queue.cancel(task_id)
+}
+
+#[tauri::command]
+pub async fn pause_task(task_id: u64, queue: tauri::State<'_, TaskQueue>) -> Result<(), String> {
+    queue.pause(task_id)
+}
+
+#[tauri::command]
+pub async fn resume_task(task_id: u64, queue: tauri::State<'_, TaskQueue>) -> Result<(), String> {
+    queue.resume(task_id).await
+}
+
+#[tauri::command]
+pub async fn clear_finished_tasks(queue: tauri::State<'_, TaskQueue>) -> Result<(), String> {
+    queue.clear_finished();
+    Ok(())
+}
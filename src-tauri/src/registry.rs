@@ -0,0 +1,436 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    games::{check_game_key, payday2},
+    lib::{error::InstallError, main_xml::MainXml, mod_txt::ModTxt, ExtractedMod},
+};
+
+/// Where a mod was installed to inside the game directory
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallTarget {
+    /// `{game}/mods` (BLT mods, detected by `mod.txt`)
+    Mods,
+    /// `{game}/assets/mod_overrides` (BeardLib / override mods, detected by `main.xml`)
+    ModOverrides,
+    /// `{game}` itself, for files that go next to the game's executable (e.g. SuperBLT's DLL)
+    Game,
+}
+
+impl InstallTarget {
+    /// The directory that roots with this target are installed into
+    pub fn dir(self, game_dir: &Path) -> PathBuf {
+        match self {
+            InstallTarget::Mods => game_dir.join("mods"),
+            InstallTarget::ModOverrides => game_dir.join("assets").join("mod_overrides"),
+            InstallTarget::Game => game_dir.to_path_buf(),
+        }
+    }
+}
+
+/// The version information ModWorkshop gives us for a mod
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ModRelease {
+    pub version: Option<String>,
+    pub updated_at: Option<String>,
+    /// When the author last bumped the mod (usually because of a new upload)
+    pub bumped_at: Option<String>,
+}
+
+impl ModRelease {
+    /// Whether this release is newer than the `installed` one.
+    ///
+    /// The bump (or update) timestamps decide: only a release bumped after the installed one is
+    /// an update, unless its version is older (e.g. the author went back to a previous version).
+    /// Versions are only compared on their own if there are no timestamps to go by.
+    /// ModWorkshop timestamps are ISO 8601 in UTC, so they compare as strings.
+    pub fn is_newer_than(&self, installed: &ModRelease) -> bool {
+        let versions = match (non_empty(&self.version), non_empty(&installed.version)) {
+            (Some(latest), Some(current)) => Some(compare_versions(latest, current)),
+            _ => None,
+        };
+
+        match (self.last_updated(), installed.last_updated()) {
+            (Some(latest), Some(current)) => latest > current && versions != Some(Ordering::Less),
+            _ => versions == Some(Ordering::Greater),
+        }
+    }
+
+    fn last_updated(&self) -> Option<&str> {
+        non_empty(&self.bumped_at).or(non_empty(&self.updated_at))
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// A part of a version string, text sorts before numbers so `1.2-beta` comes before `1.2`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionPart {
+    Text(String),
+    Number(u64),
+}
+
+/// Compares two version strings the way modders write them: a leading `v` is ignored, parts
+/// are split on `.`, `-`, `_` and `+`, numbers compare as numbers, and missing parts count as 0
+/// (so `v1.2` and `1.2.0` are the same version).
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn parts(version: &str) -> Vec<VersionPart> {
+        version
+            .trim()
+            .trim_start_matches(['v', 'V'])
+            .split(['.', '-', '_', '+', ' '])
+            .filter(|part| !part.is_empty())
+            .map(|part| match part.parse() {
+                Ok(number) => VersionPart::Number(number),
+                Err(_) => VersionPart::Text(part.to_lowercase()),
+            })
+            .collect()
+    }
+
+    let (a, b) = (parts(a), parts(b));
+    for index in 0..a.len().max(b.len()) {
+        let zero = VersionPart::Number(0);
+        let ordering = a
+            .get(index)
+            .unwrap_or(&zero)
+            .cmp(b.get(index).unwrap_or(&zero));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A directory that a mod was installed to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModRoot {
+    pub target: InstallTarget,
+    pub install_dir: PathBuf,
+}
+
+/// A file that was already there when a mod was installed, and that the mod replaced
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OverwrittenFile {
+    pub path: PathBuf,
+    /// Where its previous contents are kept until the mod is uninstalled, see
+    /// [`StorageLayout::replaced_dir`](crate::lib::storage::StorageLayout::replaced_dir)
+    pub backup: PathBuf,
+}
+
+/// Everything we know about a mod that the manager installed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstalledMod {
+    /// The ModWorkshop id of the mod
+    pub id: u32,
+    pub name: String,
+    #[serde(flatten)]
+    pub release: ModRelease,
+    pub target: InstallTarget,
+    /// The root directory of the mod, e.g. `{game}/mods/SomeMod`, or the file itself for
+    /// [`InstallTarget::Game`] roots
+    pub install_dir: PathBuf,
+    /// Any other roots, for archives that bundle several mods (e.g. a BLT mod and its mod_overrides)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_roots: Vec<ModRoot>,
+    /// Every file and directory that was written during the install, in all roots
+    pub files: Vec<PathBuf>,
+    /// The files in `files` that were already there before the install, they're put back when
+    /// the mod is uninstalled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overwrote: Vec<OverwrittenFile>,
+    /// Unix timestamp (seconds) of when the mod was installed
+    pub installed_at: u64,
+    /// Where the mod's directory was moved to while it is disabled, `None` while it is enabled
+    #[serde(default)]
+    pub disabled_path: Option<PathBuf>,
+    /// Files of the mod that lose a conflict to a higher priority mod. They are kept in
+    /// [`StorageLayout::overridden_dir`](crate::lib::storage::StorageLayout::overridden_dir)
+    /// instead of the game directory, see [`deploy`](crate::lib::priority::deploy).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overridden: Vec<PathBuf>,
+    /// What the mod says about itself in its `mod.txt`, for BLT mods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mod_txt: Option<ModTxt>,
+    /// What the mod says about itself in its `main.xml`, for BeardLib mods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_xml: Option<MainXml>,
+}
+
+impl InstalledMod {
+    pub fn new(
+        id: u32,
+        name: String,
+        release: ModRelease,
+        target: InstallTarget,
+        install_dir: PathBuf,
+        files: Vec<PathBuf>,
+    ) -> Self {
+        InstalledMod {
+            id,
+            name,
+            release,
+            target,
+            install_dir,
+            extra_roots: Vec::new(),
+            files,
+            overwrote: Vec::new(),
+            installed_at: unix_now(),
+            disabled_path: None,
+            overridden: Vec::new(),
+            mod_txt: None,
+            main_xml: None,
+        }
+    }
+
+    /// Records what [`install_staged_mod`](crate::lib::install_staged_mod) installed, `None` if it didn't install any roots
+    pub fn from_extracted(
+        id: u32,
+        name: String,
+        release: ModRelease,
+        extracted: ExtractedMod,
+    ) -> Option<Self> {
+        let mut roots = extracted.roots.into_iter();
+        let primary = roots.next()?;
+
+        let mut installed = Self::new(
+            id,
+            name,
+            release,
+            primary.target,
+            primary.install_dir,
+            primary.files,
+        );
+        installed.overwrote = primary.overwrote;
+        for root in roots {
+            installed.files.extend(root.files);
+            installed.overwrote.extend(root.overwrote);
+            installed.extra_roots.push(ModRoot {
+                target: root.target,
+                install_dir: root.install_dir,
+            });
+        }
+
+        Some(installed)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.disabled_path.is_none()
+    }
+
+    /// Every root of the mod, the primary one (`target` and `install_dir`) first
+    pub fn roots(&self) -> Vec<ModRoot> {
+        let primary = ModRoot {
+            target: self.target,
+            install_dir: self.install_dir.clone(),
+        };
+        std::iter::once(primary)
+            .chain(self.extra_roots.iter().cloned())
+            .collect()
+    }
+
+    /// Where the roots of the mod are right now: where they're installed, or where they're kept
+    /// while the mod is disabled
+    pub fn current_root_dirs(&self) -> Vec<(InstallTarget, PathBuf)> {
+        self.roots()
+            .into_iter()
+            .enumerate()
+            .map(|(index, root)| {
+                let dir = self.disabled_root_path(index).unwrap_or(root.install_dir);
+                (root.target, dir)
+            })
+            .collect()
+    }
+
+    /// Reads the metadata the mod ships, from the `mod.txt` of its first BLT root and the
+    /// `main.xml` of its first BeardLib root
+    pub fn read_metadata(&mut self) {
+        let roots = self.current_root_dirs();
+        self.mod_txt = roots
+            .iter()
+            .filter(|(target, _)| *target == InstallTarget::Mods)
+            .find_map(|(_, dir)| ModTxt::load(&dir.join("mod.txt")));
+        self.main_xml = roots
+            .iter()
+            .filter(|(target, _)| *target == InstallTarget::ModOverrides)
+            .find_map(|(_, dir)| MainXml::load(&dir.join("main.xml")));
+    }
+
+    /// Whether `path` is inside one of the mod's roots
+    pub fn owns(&self, path: &Path) -> bool {
+        self.roots()
+            .iter()
+            .any(|root| path.starts_with(&root.install_dir))
+    }
+
+    /// Where root `index` (in [`roots`](Self::roots) order) is kept while the mod is disabled.
+    ///
+    /// The primary root is moved to `disabled_path` itself, the others next to it as `{id}.{index}`.
+    pub fn disabled_root_path(&self, index: usize) -> Option<PathBuf> {
+        let disabled_path = self.disabled_path.as_ref()?;
+        if index == 0 {
+            return Some(disabled_path.clone());
+        }
+
+        let mut name = disabled_path.file_name()?.to_os_string();
+        name.push(format!(".{index}"));
+        Some(disabled_path.with_file_name(name))
+    }
+}
+
+/// Held by whoever is changing a registry, so tasks running at the same time don't overwrite
+/// each other's changes
+static REGISTRY_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The list of mods the manager has installed for a single game, keyed by ModWorkshop id.
+///
+/// This is saved to `{config_dir}/void_mod_manager/registry/{game}.json`. Use
+/// [`lock`](Self::lock) to change it, [`load`](Self::load) is only for reading.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ModRegistry {
+    #[serde(skip)]
+    path: PathBuf,
+    pub mods: BTreeMap<u32, InstalledMod>,
+}
+
+impl ModRegistry {
+    /// Loads the registry for `game` (e.g. `"pd2"`), or an empty one if nothing has been installed yet
+    pub fn load(game: &str) -> Result<Self, String> {
+        Self::load_from(Self::registry_file_path(game)?)
+    }
+
+    /// Waits for any other change to the registry of `game` to finish, then loads it to be changed.
+    ///
+    /// Nothing else can change the registry until the returned guard is dropped, so the slow
+    /// parts of a task (downloading, copying files) should be done before taking it.
+    pub async fn lock(game: &str) -> Result<RegistryGuard, String> {
+        Self::lock_from(Self::registry_file_path(game)?).await
+    }
+
+    pub async fn lock_from(path: PathBuf) -> Result<RegistryGuard, String> {
+        let lock = REGISTRY_LOCK.lock().await;
+        Ok(RegistryGuard {
+            registry: Self::load_from(path)?,
+            _lock: lock,
+        })
+    }
+
+    pub fn load_from(path: PathBuf) -> Result<Self, String> {
+        debug!("Loading mod registry from {}", path.display());
+        if !path.exists() {
+            info!("No mod registry found, starting a new one");
+            return Ok(ModRegistry {
+                path,
+                mods: BTreeMap::new(),
+            });
+        }
+
+        let data = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut registry: ModRegistry = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+        registry.path = path;
+        Ok(registry)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        info!("Saving mod registry...");
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, data).map_err(|e| e.to_string())
+    }
+
+    /// Adds (or replaces) a mod in the registry
+    pub fn insert(&mut self, installed: InstalledMod) {
+        debug!("Registering mod {} ({})", installed.name, installed.id);
+        self.mods.insert(installed.id, installed);
+    }
+
+    pub fn get(&self, id: u32) -> Option<&InstalledMod> {
+        self.mods.get(&id)
+    }
+
+    /// Fails if mod `id` is installed, enabled or not. Installing it again would replace its
+    /// manifest with one that doesn't know about the files already there.
+    pub fn ensure_not_installed(&self, id: u32) -> Result<(), InstallError> {
+        match self.get(id) {
+            Some(_) => Err(InstallError::AlreadyInstalled),
+            None => Ok(()),
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<InstalledMod> {
+        self.mods.remove(&id)
+    }
+
+    fn registry_file_path(game: &str) -> Result<PathBuf, String> {
+        check_game_key(game)?;
+        let mut path = dirs::config_dir().ok_or("Failed to find the config directory")?;
+        path.push("void_mod_manager");
+        path.push("registry");
+        path.push(format!("{game}.json"));
+        Ok(path)
+    }
+}
+
+/// A registry loaded with [`ModRegistry::lock`], no one else can change it while this is around
+pub struct RegistryGuard {
+    registry: ModRegistry,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Deref for RegistryGuard {
+    type Target = ModRegistry;
+
+    fn deref(&self) -> &ModRegistry {
+        &self.registry
+    }
+}
+
+impl DerefMut for RegistryGuard {
+    fn deref_mut(&mut self) -> &mut ModRegistry {
+        &mut self.registry
+    }
+}
+
+/// The current Unix timestamp in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[tauri::command]
+pub async fn get_installed_mods(game: Option<String>) -> Result<Vec<InstalledMod>, String> {
+    let registry = ModRegistry::load(game.as_deref().unwrap_or(payday2::GAME_KEY))?;
+    Ok(registry.mods.into_values().map(with_metadata).collect())
+}
+
+#[tauri::command]
+pub async fn get_installed_mod(
+    id: u32,
+    game: Option<String>,
+) -> Result<Option<InstalledMod>, String> {
+    let registry = ModRegistry::load(game.as_deref().unwrap_or(payday2::GAME_KEY))?;
+    Ok(registry.get(id).cloned().map(with_metadata))
+}
+
+/// Fills in the metadata of mods installed before it was recorded
+fn with_metadata(mut installed: InstalledMod) -> InstalledMod {
+    if installed.mod_txt.is_none() && installed.main_xml.is_none() {
+        installed.read_metadata();
+    }
+    installed
+}
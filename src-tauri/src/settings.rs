@@ -1,21 +1,36 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use log::info;
 use serde::{Deserialize, Serialize};
 
+use crate::lib::storage::default_download_path;
+
+/// The `download_path` older versions used by default, it's cleared on reboot on most systems
+const OLD_DEFAULT_DOWNLOAD_PATH: &str = "/tmp/.void/pd2";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AppSettings {
     theme: Option<String>,
     pub download_path: Option<PathBuf>,
     show_debug_options: Option<bool>,
+    /// The active profile of each game, keyed by game (e.g. `"pd2"`)
+    pub active_profiles: Option<BTreeMap<String, String>>,
+    /// How many downloads the queue runs at the same time
+    pub max_concurrent_downloads: Option<usize>,
 }
 
 impl AppSettings {
     fn default() -> Self {
         AppSettings {
             theme: Some("Dark".to_string()),
-            download_path: Some(PathBuf::from("/tmp/.void/pd2".to_string())),
+            download_path: Some(default_download_path()),
             show_debug_options: Some(false),
+            active_profiles: Some(BTreeMap::new()),
+            max_concurrent_downloads: Some(2),
         }
     }
 
@@ -37,6 +52,19 @@ impl AppSettings {
             let _ = self.save();
         }
 
+        if self
+            .download_path
+            .as_deref()
+            .is_some_and(|path| path == Path::new(OLD_DEFAULT_DOWNLOAD_PATH))
+        {
+            self.download_path = Some(default_download_path());
+            info!(
+                "Moved 'download_path' off the old default to: {:?}",
+                self.download_path
+            );
+            let _ = self.save();
+        }
+
         if self.show_debug_options.is_none() {
             self.show_debug_options = defaults.show_debug_options;
             info!(
@@ -45,6 +73,24 @@ impl AppSettings {
             );
             let _ = self.save();
         }
+
+        if self.active_profiles.is_none() {
+            self.active_profiles = defaults.active_profiles;
+            info!(
+                "Set missing field 'active_profiles' to default: {:?}",
+                self.active_profiles
+            );
+            let _ = self.save();
+        }
+
+        if self.max_concurrent_downloads.is_none() {
+            self.max_concurrent_downloads = defaults.max_concurrent_downloads;
+            info!(
+                "Set missing field 'max_concurrent_downloads' to default: {:?}",
+                self.max_concurrent_downloads
+            );
+            let _ = self.save();
+        }
     }
 
     fn load() -> Result<Self, String> {
@@ -1,8 +1,1974 @@
 #[cfg(test)]
 pub mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::Write,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{
+        cache::{sha256_file, ArchiveCache},
+        conflicts::{ConflictIndex, ConflictKey, FileConflict},
+        games::{check_game_key, payday2},
+        lib::{
+            archive::{enclosed_path, escapes, rar_entries, ArchiveFormat},
+            dependencies::{
+                install_order, missing_requirements, modworkshop_id, requirements,
+                staged_requirements, Requirement, BEARDLIB_ID,
+            },
+            download::{
+                content_disposition_file_name, download_to_file, partial_path, url_file_name,
+                DownloadProgress, DownloadedFile,
+            },
+            error::InstallError,
+            events::{ModTaskEvent, TaskControl, TaskEmitter, TaskKind, TaskRef, TaskSignal},
+            install_staged_mod,
+            integrity::{validate_zip, verify_file, ExpectedFile},
+            layout::{find_mod_roots, InstallLayout, LayoutKind, ModType, PlannedFile},
+            main_xml::{AssetUpdates, MainXml, MainXmlDependency},
+            mod_txt::{Hook, ModTxt},
+            plan::InstallPlan,
+            priority::{deploy, plan_deployment, Deployment},
+            storage::StorageLayout,
+            toggle::{disable_mod_files, enable_mod_files},
+            uninstall::{hand_over_files, remove_mod_files},
+            update::{find_user_files, replace_installed_mod},
+            ExtractedMod, ExtractedRoot,
+        },
+        profiles::{plan_switch, Profile, ProfileStore, ProfileSwitch},
+        queue::{QueuedTask, TaskQueue, TaskRunner, TaskStatus},
+        registry::{InstallTarget, InstalledMod, ModRegistry, ModRelease, ModRoot},
+    };
+
+    /// Creates an empty, unique directory in the system temp dir for a test to play in
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("void_test_{}_{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a zip containing `files` (path in the archive, contents)
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Gets one of the archives in `fixtures/archives`
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join("archives")
+            .join(name)
+    }
 
     #[tokio::test]
     async fn test_unzip_mod_extract_specific_file_success() {
         assert!(true);
     }
+
+    #[test]
+    fn test_registry_round_trip() {
+        let dir = test_dir("registry_round_trip");
+        let path = dir.join("pd2.json");
+
+        let mut registry = ModRegistry::load_from(path.clone()).unwrap();
+        assert!(registry.mods.is_empty());
+
+        registry.insert(InstalledMod::new(
+            42,
+            "Some Mod".to_string(),
+            ModRelease {
+                version: Some("1.0".to_string()),
+                ..Default::default()
+            },
+            InstallTarget::Mods,
+            dir.join("mods/Some Mod"),
+            vec![dir.join("mods/Some Mod"), dir.join("mods/Some Mod/mod.txt")],
+        ));
+        registry.save().unwrap();
+
+        let loaded = ModRegistry::load_from(path).unwrap();
+        let installed = loaded.get(42).expect("mod should be registered");
+        assert_eq!(installed.name, "Some Mod");
+        assert_eq!(installed.target, InstallTarget::Mods);
+        assert_eq!(installed.files.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_registry_lock_keeps_concurrent_changes() {
+        let dir = test_dir("registry_lock");
+        let path = dir.join("pd2.json");
+
+        let tasks: Vec<_> = (1..=8)
+            .map(|id| {
+                let path = path.clone();
+                let dir = dir.clone();
+                tokio::spawn(async move {
+                    let mut registry = ModRegistry::lock_from(path).await.unwrap();
+                    // Give the other tasks a chance to load a stale copy, if they could
+                    tokio::task::yield_now().await;
+                    let install_dir = dir.join(format!("mods/Mod {id}"));
+                    registry.insert(InstalledMod::new(
+                        id,
+                        format!("Mod {id}"),
+                        ModRelease::default(),
+                        InstallTarget::Mods,
+                        install_dir.clone(),
+                        vec![install_dir],
+                    ));
+                    registry.save().unwrap();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let registry = ModRegistry::load_from(path).unwrap();
+        assert_eq!(
+            registry.mods.keys().copied().collect::<Vec<_>>(),
+            (1..=8).collect::<Vec<u32>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_mod_files_only_touches_manifest() {
+        let dir = test_dir("remove_mod_files");
+        let install_dir = dir.join("mods/Some Mod");
+        std::fs::create_dir_all(install_dir.join("lua")).unwrap();
+        std::fs::write(install_dir.join("mod.txt"), "{}").unwrap();
+        std::fs::write(install_dir.join("lua/main.lua"), "").unwrap();
+        // Written by the user after installing, should survive the uninstall
+        std::fs::write(install_dir.join("settings.json"), "{}").unwrap();
+        // Not part of the mod at all
+        std::fs::write(dir.join("mods/other.txt"), "").unwrap();
+
+        let installed = InstalledMod::new(
+            1,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            InstallTarget::Mods,
+            install_dir.clone(),
+            vec![
+                install_dir.clone(),
+                install_dir.join("lua"),
+                install_dir.join("mod.txt"),
+                install_dir.join("lua/main.lua"),
+                dir.join("mods/other.txt"),
+            ],
+        );
+
+        let removed = remove_mod_files(&installed, |_, _| {}).await.unwrap();
+
+        assert_eq!(removed, 3);
+        assert!(!install_dir.join("lua").exists());
+        assert!(install_dir.join("settings.json").exists());
+        assert!(dir.join("mods/other.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_reinstall_is_rejected_and_uninstall_restores_overwritten_files() {
+        let dir = test_dir("overwritten_files");
+        let game_dir = dir.join("PAYDAY 2");
+        let replaced_dir = dir.join("replaced/1");
+        let staged = dir.join("staged");
+        touch_all(&staged, &["Some Mod/mod.txt"]);
+        std::fs::write(staged.join("Some Mod/main.lua"), "from the mod").unwrap();
+
+        // Put there by hand before, the mod overwrites it
+        let install_dir = game_dir.join("mods/Some Mod");
+        std::fs::create_dir_all(&install_dir).unwrap();
+        std::fs::write(install_dir.join("main.lua"), "by hand").unwrap();
+
+        let layout = InstallLayout::plan(&staged, &game_dir).unwrap();
+        let extracted = install_staged_mod(&staged, layout, 1, &replaced_dir)
+            .await
+            .unwrap();
+        let installed = InstalledMod::from_extracted(
+            1,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            extracted,
+        )
+        .unwrap();
+
+        // Every file is recorded, the one that was there is flagged and backed up
+        assert!(installed.files.contains(&install_dir.join("main.lua")));
+        assert!(installed.files.contains(&install_dir.join("mod.txt")));
+        assert_eq!(installed.overwrote.len(), 1);
+        assert_eq!(installed.overwrote[0].path, install_dir.join("main.lua"));
+        assert_eq!(
+            std::fs::read_to_string(&installed.overwrote[0].backup).unwrap(),
+            "by hand"
+        );
+
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        registry.insert(installed.clone());
+        assert!(matches!(
+            registry.ensure_not_installed(1),
+            Err(InstallError::AlreadyInstalled)
+        ));
+        assert!(registry.ensure_not_installed(2).is_ok());
+
+        remove_mod_files(&installed, |_, _| {}).await.unwrap();
+
+        assert!(!install_dir.join("mod.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(install_dir.join("main.lua")).unwrap(),
+            "by hand"
+        );
+        assert!(!installed.overwrote[0].backup.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_install_removes_partial_root() {
+        let dir = test_dir("failed_install");
+        let staged = dir.join("staged");
+        let game_dir = dir.join("PAYDAY 2");
+        std::fs::create_dir_all(game_dir.join("mods")).unwrap();
+        touch_all(
+            &staged,
+            &["A Mod/mod.txt", "B Mod/mod.txt", "B Mod/lua/main.lua"],
+        );
+        // Copying a link to nothing fails half way through the root
+        std::os::unix::fs::symlink(dir.join("missing"), staged.join("B Mod/lua/broken")).unwrap();
+
+        let layout = InstallLayout::plan(&staged, &game_dir).unwrap();
+        assert!(
+            install_staged_mod(&staged, layout, 1, &dir.join("replaced"))
+                .await
+                .is_err()
+        );
+
+        assert!(!game_dir.join("mods/A Mod").exists());
+        assert!(!game_dir.join("mods/B Mod").exists());
+    }
+
+    #[test]
+    fn test_release_is_newer_than() {
+        let installed = ModRelease {
+            version: Some("1.0".to_string()),
+            updated_at: Some("2024-01-01T00:00:00.000000Z".to_string()),
+            bumped_at: Some("2024-01-01T00:00:00.000000Z".to_string()),
+        };
+
+        let same = installed.clone();
+        assert!(!same.is_newer_than(&installed));
+
+        let bumped_at = Some("2024-03-01T00:00:00.000000Z".to_string());
+        let new_version = ModRelease {
+            version: Some("1.1".to_string()),
+            bumped_at: bumped_at.clone(),
+            ..installed.clone()
+        };
+        assert!(new_version.is_newer_than(&installed));
+        assert!(!installed.is_newer_than(&new_version));
+
+        // Re-tagged without a new upload
+        let retagged = ModRelease {
+            version: Some("1.1".to_string()),
+            ..installed.clone()
+        };
+        assert!(!retagged.is_newer_than(&installed));
+
+        // Uploaded later, but going back to an older version
+        let older = ModRelease {
+            version: Some("0.9".to_string()),
+            bumped_at: bumped_at.clone(),
+            ..installed.clone()
+        };
+        assert!(!older.is_newer_than(&installed));
+
+        // The same version written differently, e.g. read from mod.txt
+        let formatted = ModRelease {
+            version: Some("v1.0.0".to_string()),
+            ..installed.clone()
+        };
+        assert!(!formatted.is_newer_than(&installed));
+        assert!(!installed.is_newer_than(&formatted));
+
+        // No version on either side, fall back to the bump date
+        let bumped = ModRelease {
+            version: Some("".to_string()),
+            bumped_at,
+            ..installed.clone()
+        };
+        assert!(bumped.is_newer_than(&installed));
+        assert!(!installed.is_newer_than(&bumped));
+
+        // No dates, the versions decide
+        let undated = |version: &str| ModRelease {
+            version: Some(version.to_string()),
+            ..Default::default()
+        };
+        assert!(undated("1.10").is_newer_than(&undated("v1.9")));
+        assert!(undated("1.2").is_newer_than(&undated("1.2-beta")));
+        assert!(!undated("1.2").is_newer_than(&undated("V1.2")));
+        assert!(!undated("1.1").is_newer_than(&undated("1.2")));
+    }
+
+    #[test]
+    fn test_find_user_files() {
+        let dir = test_dir("find_user_files");
+        let install_dir = dir.join("mods/Some Mod");
+        std::fs::create_dir_all(install_dir.join("saves")).unwrap();
+        std::fs::write(install_dir.join("mod.txt"), "{}").unwrap();
+        std::fs::write(install_dir.join("saves/defaults.json"), "{}").unwrap();
+        std::fs::write(install_dir.join("options.json"), "{}").unwrap();
+        std::fs::write(install_dir.join("settings.json"), "{}").unwrap();
+
+        let installed = InstalledMod::new(
+            1,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            InstallTarget::Mods,
+            install_dir.clone(),
+            vec![
+                install_dir.clone(),
+                install_dir.join("mod.txt"),
+                install_dir.join("saves/defaults.json"),
+                install_dir.join("options.json"),
+            ],
+        );
+        // Edited after the install, the new version's copy still wins
+        std::fs::write(install_dir.join("mod.txt"), r#"{"name": "edited"}"#).unwrap();
+        // Missing from the manifest, but the new release ships it
+        std::fs::write(install_dir.join("main.lua"), "").unwrap();
+        let new_root = dir.join("staged/Some Mod");
+        touch_all(&new_root, &["mod.txt", "main.lua"]);
+
+        let mut user_files = find_user_files(&installed, &install_dir, Some(&new_root));
+        user_files.sort();
+        assert_eq!(
+            user_files,
+            vec![
+                PathBuf::from("saves/defaults.json"),
+                PathBuf::from("settings.json")
+            ]
+        );
+
+        let mut user_files = find_user_files(&installed, &install_dir, None);
+        user_files.sort();
+        assert_eq!(
+            user_files,
+            vec![
+                PathBuf::from("main.lua"),
+                PathBuf::from("saves/defaults.json"),
+                PathBuf::from("settings.json")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_update_rolls_back() {
+        let dir = test_dir("failed_update");
+        let game_dir = dir.join("PAYDAY 2");
+        let storage = StorageLayout::new(&dir.join("downloads"), "pd2");
+        let install_dir = game_dir.join("mods/Some Mod");
+        std::fs::create_dir_all(&install_dir).unwrap();
+        std::fs::write(install_dir.join("readme.txt"), "by hand").unwrap();
+
+        let old = dir.join("old");
+        std::fs::create_dir_all(old.join("Some Mod")).unwrap();
+        std::fs::write(old.join("Some Mod/mod.txt"), "old").unwrap();
+        std::fs::write(old.join("Some Mod/readme.txt"), "old").unwrap();
+        let layout = InstallLayout::plan(&old, &game_dir).unwrap();
+        let extracted = install_staged_mod(&old, layout, 1, &storage.replaced_dir(1))
+            .await
+            .unwrap();
+        let installed = InstalledMod::from_extracted(
+            1,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            extracted,
+        )
+        .unwrap();
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        registry.insert(installed.clone());
+        registry.save().unwrap();
+        std::fs::write(install_dir.join("settings.json"), "user").unwrap();
+
+        // Copying a link to nothing fails half way through the new version
+        let new = dir.join("new");
+        std::fs::create_dir_all(new.join("Some Mod")).unwrap();
+        std::fs::write(new.join("Some Mod/mod.txt"), "new").unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), new.join("Some Mod/broken")).unwrap();
+        let layout = InstallLayout::plan(&new, &game_dir).unwrap();
+
+        assert!(replace_installed_mod(&installed, &new, layout, &storage)
+            .await
+            .is_err());
+
+        let read = |name: &str| std::fs::read_to_string(install_dir.join(name)).unwrap();
+        assert_eq!(read("mod.txt"), "old");
+        assert_eq!(read("readme.txt"), "old");
+        assert_eq!(read("settings.json"), "user");
+        assert!(!install_dir.join("broken").exists());
+        assert!(!storage.backup_dir(1).exists());
+
+        let registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        let entry = registry.get(1).unwrap();
+        assert_eq!(entry.files, installed.files);
+        assert_eq!(entry.overwrote, installed.overwrote);
+
+        // The file the old version overwrote is still backed up, and comes back on uninstall
+        remove_mod_files(entry, |_, _| {}).await.unwrap();
+        assert_eq!(read("readme.txt"), "by hand");
+        assert!(!install_dir.join("mod.txt").exists());
+    }
+
+    #[test]
+    fn test_plan_profile_switch() {
+        let dir = test_dir("plan_profile_switch");
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        for id in [1, 2, 3] {
+            registry.insert(InstalledMod::new(
+                id,
+                format!("Mod {id}"),
+                ModRelease::default(),
+                InstallTarget::Mods,
+                dir.join(format!("mods/{id}")),
+                Vec::new(),
+            ));
+        }
+        registry.mods.get_mut(&3).unwrap().disabled_path = Some(dir.join("disabled/3"));
+
+        let profile = Profile {
+            name: "Heisting".to_string(),
+            // 4 isn't installed, and should be ignored
+            mods: vec![1, 3, 4],
+            priority: Vec::new(),
+        };
+
+        assert_eq!(
+            plan_switch(&registry, &profile),
+            ProfileSwitch {
+                enable: vec![3],
+                disable: vec![2],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_enable_mod_files() {
+        let dir = test_dir("disable_enable");
+        let install_dir = dir.join("mods/Some Mod");
+        std::fs::create_dir_all(&install_dir).unwrap();
+        std::fs::write(install_dir.join("mod.txt"), "{}").unwrap();
+
+        let mut installed = InstalledMod::new(
+            7,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            InstallTarget::Mods,
+            install_dir.clone(),
+            vec![install_dir.clone(), install_dir.join("mod.txt")],
+        );
+
+        disable_mod_files(&mut installed, &dir.join("disabled"))
+            .await
+            .unwrap();
+        assert!(!installed.is_enabled());
+        assert!(!install_dir.exists());
+        assert!(dir.join("disabled/7/mod.txt").exists());
+
+        enable_mod_files(&mut installed).await.unwrap();
+        assert!(installed.is_enabled());
+        assert!(install_dir.join("mod.txt").exists());
+        assert!(!dir.join("disabled/7").exists());
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_enable_every_root() {
+        let dir = test_dir("disable_enable_roots");
+        let mod_dir = dir.join("mods/Some Mod");
+        let override_dir = dir.join("assets/mod_overrides/Some Override");
+        std::fs::create_dir_all(&mod_dir).unwrap();
+        std::fs::create_dir_all(&override_dir).unwrap();
+        std::fs::write(mod_dir.join("mod.txt"), "{}").unwrap();
+        std::fs::write(override_dir.join("texture.dds"), "").unwrap();
+
+        let extracted = ExtractedMod {
+            roots: vec![
+                ExtractedRoot {
+                    source: PathBuf::from("Some Mod"),
+                    target: InstallTarget::Mods,
+                    install_dir: mod_dir.clone(),
+                    files: vec![mod_dir.clone(), mod_dir.join("mod.txt")],
+                    overwrote: Vec::new(),
+                },
+                ExtractedRoot {
+                    source: PathBuf::from("mod_overrides/Some Override"),
+                    target: InstallTarget::ModOverrides,
+                    install_dir: override_dir.clone(),
+                    files: vec![override_dir.clone(), override_dir.join("texture.dds")],
+                    overwrote: Vec::new(),
+                },
+            ],
+        };
+        let mut installed = InstalledMod::from_extracted(
+            8,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            extracted,
+        )
+        .unwrap();
+        assert_eq!(installed.roots().len(), 2);
+        assert_eq!(installed.files.len(), 4);
+        assert!(installed.owns(&override_dir.join("texture.dds")));
+        assert!(!installed.owns(&dir.join("mods/Other Mod")));
+
+        disable_mod_files(&mut installed, &dir.join("disabled"))
+            .await
+            .unwrap();
+        assert!(!mod_dir.exists());
+        assert!(!override_dir.exists());
+        assert!(dir.join("disabled/8/mod.txt").exists());
+        assert!(dir.join("disabled/8.1/texture.dds").exists());
+
+        enable_mod_files(&mut installed).await.unwrap();
+        assert!(mod_dir.join("mod.txt").exists());
+        assert!(override_dir.join("texture.dds").exists());
+        assert!(!dir.join("disabled/8.1").exists());
+
+        // A root that can't be moved back leaves the mod disabled, with nothing half moved
+        disable_mod_files(&mut installed, &dir.join("disabled"))
+            .await
+            .unwrap();
+        std::fs::create_dir_all(&override_dir).unwrap();
+        assert!(enable_mod_files(&mut installed).await.is_err());
+        assert!(!installed.is_enabled());
+        assert!(!mod_dir.exists());
+        assert!(dir.join("disabled/8/mod.txt").exists());
+    }
+
+    #[test]
+    fn test_find_every_mod_root() {
+        let dir = test_dir("find_mod_roots");
+        let files = [
+            "Bundle/readme.txt",
+            // A BLT mod with a nested main.xml that belongs to it
+            "Bundle/Some Mod/mod.txt",
+            "Bundle/Some Mod/assets/main.xml",
+            // A BeardLib mod
+            "Bundle/Some Map/main.xml",
+            // Plain overrides, no main.xml
+            "Bundle/mod_overrides/Some Textures/units/texture.dds",
+            "Bundle/mod_overrides/Some Sounds/sound.ogg",
+        ];
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let bundle = dir.join("Bundle");
+        assert_eq!(
+            find_mod_roots(&dir),
+            vec![
+                (bundle.join("Some Map"), InstallTarget::ModOverrides),
+                (bundle.join("Some Mod"), InstallTarget::Mods),
+                (
+                    bundle.join("mod_overrides/Some Sounds"),
+                    InstallTarget::ModOverrides
+                ),
+                (
+                    bundle.join("mod_overrides/Some Textures"),
+                    InstallTarget::ModOverrides
+                ),
+            ]
+        );
+
+        assert!(find_mod_roots(&dir.join("Bundle/mod_overrides/Some Sounds")).is_empty());
+    }
+
+    /// Creates empty files at `files`, relative to `dir`
+    fn touch_all(dir: &Path, files: &[&str]) {
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_plan_game_tree_layout() {
+        let dir = test_dir("plan_game_tree");
+        let extracted = dir.join("extracted");
+        let game_dir = dir.join("PAYDAY 2");
+        touch_all(
+            &extracted,
+            &[
+                "Release/readme.txt",
+                "Release/WSOCK32.dll",
+                "Release/mods/readme.txt",
+                "Release/mods/Some Mod/mod.txt",
+                "Release/mods/Some Mod/lua/main.lua",
+                "Release/Assets/Mod_Overrides/Some Textures/units/texture.dds",
+            ],
+        );
+
+        let layout = InstallLayout::plan(&extracted, &game_dir).unwrap();
+        assert_eq!(layout.kind, LayoutKind::GameTree);
+
+        let roots: Vec<_> = layout
+            .roots
+            .iter()
+            .map(|r| (r.source.clone(), r.target, r.install_dir.clone()))
+            .collect();
+        assert_eq!(
+            roots,
+            vec![
+                (
+                    PathBuf::from("Release/WSOCK32.dll"),
+                    InstallTarget::Game,
+                    game_dir.join("WSOCK32.dll")
+                ),
+                (
+                    PathBuf::from("Release/Assets/Mod_Overrides/Some Textures"),
+                    InstallTarget::ModOverrides,
+                    game_dir.join("assets/mod_overrides/Some Textures")
+                ),
+                (
+                    PathBuf::from("Release/mods/Some Mod"),
+                    InstallTarget::Mods,
+                    game_dir.join("mods/Some Mod")
+                ),
+            ]
+        );
+
+        let mod_types: Vec<_> = layout.roots.iter().map(|r| r.mod_type).collect();
+        assert_eq!(
+            mod_types,
+            vec![ModType::GameFiles, ModType::ModOverride, ModType::BltMod]
+        );
+
+        assert_eq!(
+            layout.roots[0].files,
+            vec![PlannedFile {
+                source: PathBuf::from("Release/WSOCK32.dll"),
+                destination: game_dir.join("WSOCK32.dll"),
+            }]
+        );
+        assert_eq!(
+            layout.roots[2].files,
+            vec![
+                PlannedFile {
+                    source: PathBuf::from("Release/mods/Some Mod/mod.txt"),
+                    destination: game_dir.join("mods/Some Mod/mod.txt"),
+                },
+                PlannedFile {
+                    source: PathBuf::from("Release/mods/Some Mod/lua/main.lua"),
+                    destination: game_dir.join("mods/Some Mod/lua/main.lua"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_mod_roots_layout() {
+        let dir = test_dir("plan_mod_roots");
+        let extracted = dir.join("extracted");
+        let game_dir = dir.join("PAYDAY 2");
+        // A mod's own assets directory doesn't make the archive a copy of the game directory
+        touch_all(
+            &extracted,
+            &[
+                "Some Mod/mod.txt",
+                "Some Mod/assets/mod_overrides/Bundled/texture.dds",
+            ],
+        );
+
+        let layout = InstallLayout::plan(&extracted, &game_dir).unwrap();
+        assert_eq!(layout.kind, LayoutKind::ModRoots);
+        assert_eq!(layout.roots.len(), 1);
+        assert_eq!(layout.roots[0].install_dir, game_dir.join("mods/Some Mod"));
+        assert_eq!(layout.roots[0].files.len(), 2);
+
+        // Two mods with the same name can't both be installed
+        touch_all(&extracted, &["Other/Some Mod/mod.txt"]);
+        assert!(matches!(
+            InstallLayout::plan(&extracted, &game_dir),
+            Err(InstallError::DuplicateRoot(path)) if path == game_dir.join("mods/Some Mod")
+        ));
+
+        let empty = dir.join("empty");
+        touch_all(&empty, &["readme.txt"]);
+        assert!(matches!(
+            InstallLayout::plan(&empty, &game_dir),
+            Err(InstallError::NoModRoot)
+        ));
+    }
+
+    #[test]
+    fn test_install_plan_lists_overwrites_and_round_trips() {
+        let dir = test_dir("install_plan");
+        let staged = dir.join("staging/5");
+        let game_dir = dir.join("PAYDAY 2");
+        touch_all(
+            &staged,
+            &[
+                "Some Heist/main.xml",
+                "Some Heist/levels/heist.xml",
+                "Some Overhaul/main.xml",
+            ],
+        );
+        std::fs::write(
+            staged.join("Some Heist/main.xml"),
+            "<table name=\"Some Heist\"><Narrative id=\"some_heist\"/></table>",
+        )
+        .unwrap();
+        std::fs::write(
+            staged.join("Some Overhaul/main.xml"),
+            "<table name=\"Some Overhaul\"><Hooks/></table>",
+        )
+        .unwrap();
+        // An older version of the heist is already installed
+        touch_all(&game_dir, &["assets/mod_overrides/Some Heist/main.xml"]);
+
+        let layout = InstallLayout::plan(&staged, &game_dir).unwrap();
+        let mod_types: Vec<_> = layout.roots.iter().map(|r| r.mod_type).collect();
+        assert_eq!(mod_types, vec![ModType::BeardLibMap, ModType::BeardLibMod]);
+
+        let plan = InstallPlan::new(
+            5,
+            "Some Heist".to_string(),
+            ModRelease::default(),
+            "abc".to_string(),
+            layout,
+            Vec::new(),
+            Vec::new(),
+        );
+        assert_eq!(
+            plan.overwrites,
+            vec![game_dir.join("assets/mod_overrides/Some Heist/main.xml")]
+        );
+        assert!(plan.is_staged(&staged));
+
+        let path = dir.join("staging/5.plan.json");
+        plan.save(&path).unwrap();
+        assert_eq!(InstallPlan::load(&path), Some(plan.clone()));
+
+        std::fs::remove_file(staged.join("Some Heist/levels/heist.xml")).unwrap();
+        assert!(!plan.is_staged(&staged));
+    }
+
+    #[test]
+    fn test_conflict_index() {
+        let dir = test_dir("conflict_index");
+        let overrides = dir.join("game/assets/mod_overrides");
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        for (id, name) in [(1, "Skin A"), (2, "Skin B"), (3, "Skin C")] {
+            let root = overrides.join(name);
+            registry.insert(InstalledMod::new(
+                id,
+                name.to_string(),
+                ModRelease::default(),
+                InstallTarget::ModOverrides,
+                root.clone(),
+                vec![
+                    root.join("main.xml"),
+                    root.join("units/x.texture"),
+                    root.join(format!("units/{id}.texture")),
+                ],
+            ));
+        }
+        // Disabled mods aren't in the game directory, so can't conflict
+        registry.mods.get_mut(&3).unwrap().disabled_path = Some(dir.join("disabled/3"));
+
+        let index = ConflictIndex::build(&registry);
+        // `main.xml` is at the top of each root, so it doesn't override anything
+        assert_eq!(
+            index.conflicts(),
+            vec![FileConflict {
+                key: ConflictKey::Asset(PathBuf::from("units/x.texture")),
+                mods: vec![1, 2],
+            }]
+        );
+
+        let staged = dir.join("staging/3");
+        touch_all(&staged, &["Skin C/main.xml", "Skin C/units/x.texture"]);
+        let layout = InstallLayout::plan(&staged, &dir.join("game")).unwrap();
+        assert_eq!(
+            index.check(3, &layout),
+            vec![FileConflict {
+                key: ConflictKey::Asset(PathBuf::from("units/x.texture")),
+                mods: vec![1, 2, 3],
+            }]
+        );
+        // Updating a mod doesn't conflict with its own files
+        assert_eq!(index.check(2, &layout).len(), 1);
+        assert_eq!(index.check(2, &layout)[0].mods, vec![1, 2]);
+
+        let conflict = serde_json::to_value(&index.conflicts()[0]).unwrap();
+        assert_eq!(conflict["kind"], "asset");
+        assert_eq!(conflict["path"], "units/x.texture");
+    }
+
+    #[tokio::test]
+    async fn test_deploy_conflict_priorities() {
+        let dir = test_dir("deploy_priorities");
+        let storage = StorageLayout::new(&dir, "pd2");
+        let overrides = dir.join("game/assets/mod_overrides");
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        for (id, name) in [(1, "Skin A"), (2, "Skin B")] {
+            let root = overrides.join(name);
+            touch_all(&root, &["main.xml", "units/x.texture"]);
+            std::fs::write(root.join("units/x.texture"), name).unwrap();
+
+            let mut installed = InstalledMod::new(
+                id,
+                name.to_string(),
+                ModRelease::default(),
+                InstallTarget::ModOverrides,
+                root.clone(),
+                vec![root.join("main.xml"), root.join("units/x.texture")],
+            );
+            installed.installed_at = id as u64;
+            registry.insert(installed);
+        }
+        let texture_a = overrides.join("Skin A/units/x.texture");
+        let texture_b = overrides.join("Skin B/units/x.texture");
+        let stored_a = storage.overridden_dir(1).join("Skin A/units/x.texture");
+
+        // Without a priority, the mod installed last wins
+        assert_eq!(
+            plan_deployment(&registry, &[]),
+            Deployment {
+                stash: vec![(1, texture_a.clone())],
+                restore: Vec::new(),
+            }
+        );
+
+        assert_eq!(deploy(&mut registry, &[2], &storage).await.unwrap(), 1);
+        assert!(!texture_a.exists());
+        assert_eq!(std::fs::read_to_string(&stored_a).unwrap(), "Skin A");
+        assert_eq!(registry.get(1).unwrap().overridden, vec![texture_a.clone()]);
+        // Nothing changed, so nothing moves
+        assert_eq!(deploy(&mut registry, &[2], &storage).await.unwrap(), 0);
+
+        // Swapping the order swaps the files
+        assert_eq!(deploy(&mut registry, &[1, 2], &storage).await.unwrap(), 2);
+        assert_eq!(std::fs::read_to_string(&texture_a).unwrap(), "Skin A");
+        assert!(!texture_b.exists());
+        assert!(registry.get(1).unwrap().overridden.is_empty());
+        assert_eq!(registry.get(2).unwrap().overridden, vec![texture_b.clone()]);
+
+        // The overridden file still counts as part of the mod for conflicts
+        assert_eq!(ConflictIndex::build(&registry).conflicts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_uninstall_hands_overwritten_mods_files_over() {
+        let dir = test_dir("hand_over");
+        let game_dir = dir.join("PAYDAY 2");
+        let storage = StorageLayout::new(&dir.join("downloads"), "pd2");
+        std::fs::create_dir_all(game_dir.join("mods")).unwrap();
+        let mut registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+
+        // Both bundle the same library
+        for id in 1..=2 {
+            let staged = dir.join(format!("staged/{id}"));
+            std::fs::create_dir_all(staged.join("Shared Lib")).unwrap();
+            std::fs::write(staged.join("Shared Lib/mod.txt"), "{}").unwrap();
+            std::fs::write(staged.join("Shared Lib/lib.lua"), format!("from {id}")).unwrap();
+            let layout = InstallLayout::plan(&staged, &game_dir).unwrap();
+            let extracted = install_staged_mod(&staged, layout, id, &storage.replaced_dir(id))
+                .await
+                .unwrap();
+            let mut installed = InstalledMod::from_extracted(
+                id,
+                format!("Mod {id}"),
+                ModRelease::default(),
+                extracted,
+            )
+            .unwrap();
+            installed.installed_at = id as u64;
+            registry.insert(installed);
+        }
+
+        let lib = game_dir.join("mods/Shared Lib/lib.lua");
+        let conflicts = ConflictIndex::build(&registry).conflicts();
+        assert!(conflicts.contains(&FileConflict {
+            key: ConflictKey::File(PathBuf::from("mods/Shared Lib/lib.lua")),
+            mods: vec![1, 2],
+        }));
+
+        let first = registry.get(1).unwrap().clone();
+        let kept = hand_over_files(&mut registry, &first).await.unwrap();
+        assert!(kept.contains(&lib));
+        let mut leaving = first.clone();
+        leaving.files.retain(|file| !kept.contains(file));
+        remove_mod_files(&leaving, |_, _| {}).await.unwrap();
+        registry.remove(1);
+
+        // The second mod keeps its copy, and now owns it outright
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "from 2");
+        let second = registry.get(2).unwrap().clone();
+        assert!(second.overwrote.is_empty());
+        assert!(!storage.replaced_dir(2).join("lib.lua").exists());
+
+        remove_mod_files(&second, |_, _| {}).await.unwrap();
+        assert!(!game_dir.join("mods/Shared Lib").exists());
+    }
+
+    #[test]
+    fn test_parse_loose_mod_txt() {
+        let text = r#"{
+            // Written by hand, like most of them
+            "name" : "Some Mod",
+            "description" : "Does things // but not comments",
+            "author" : "someone",
+            "version" : 1.5,
+            "blt_version" : 2,
+            /* "image" : "icon.png", */
+            "hooks" : [
+                { "hook_id" : "lib/managers/menumanager", "script_path" : "menu.lua", },
+                { "hook_id" : "lib/units/broken" },
+            ],
+            "persist_scripts" : [
+                { "global" : "SomeModLoaded", "script_path" : "persist.lua" }
+            ],
+            "updates" : [
+                {
+                    "identifier" : "somemod",
+                    "host" : { "meta" : "https://example.com/meta.json" },
+                    "install_folder" : "Some Mod",
+                }
+            ],
+            "dependencies" : {
+                "beardlib" : { "download_url" : "https://example.com/beardlib.zip" },
+            },
+        }"#;
+        // Notepad likes to start files with a byte order mark
+        let mod_txt = ModTxt::parse(&format!("\u{feff}{text}")).unwrap();
+
+        assert_eq!(mod_txt.name.as_deref(), Some("Some Mod"));
+        assert_eq!(
+            mod_txt.description.as_deref(),
+            Some("Does things // but not comments")
+        );
+        assert_eq!(mod_txt.version.as_deref(), Some("1.5"));
+        assert_eq!(mod_txt.blt_version.as_deref(), Some("2"));
+        // The hook without a script is skipped
+        assert_eq!(
+            mod_txt.hooks,
+            vec![Hook {
+                hook_id: "lib/managers/menumanager".to_string(),
+                script_path: "menu.lua".to_string(),
+            }]
+        );
+        assert_eq!(mod_txt.persist_scripts[0].global, "SomeModLoaded");
+        assert_eq!(mod_txt.updates[0].identifier, "somemod");
+        assert_eq!(
+            mod_txt.updates[0].meta_url.as_deref(),
+            Some("https://example.com/meta.json")
+        );
+        assert_eq!(
+            mod_txt.updates[0].install_folder.as_deref(),
+            Some("Some Mod")
+        );
+        assert_eq!(mod_txt.dependencies[0].identifier, "beardlib");
+        assert_eq!(
+            mod_txt.dependencies[0].download_url.as_deref(),
+            Some("https://example.com/beardlib.zip")
+        );
+
+        let listed = ModTxt::parse(
+            r#"{ "dependencies" : [ { "identifier" : "superblt", "display_name" : "SuperBLT" } ] }"#,
+        )
+        .unwrap();
+        assert_eq!(listed.dependencies[0].identifier, "superblt");
+        assert_eq!(
+            listed.dependencies[0].display_name.as_deref(),
+            Some("SuperBLT")
+        );
+
+        assert!(ModTxt::parse("[]").is_err());
+    }
+
+    #[test]
+    fn test_parse_main_xml() {
+        let main_xml = MainXml::parse(
+            r#"<table name="Some Heist" global_key="SomeHeist" priority=" 10 ">
+                <AssetUpdates id="12345" version="1.2" provider="modworkshop"/>
+                <Dependencies>
+                    <dependency name="BeardLib" provider="modworkshop" id="14924" min_ver="4.0"/>
+                    <SuperBLT/>
+                </Dependencies>
+                <Hooks directory="hooks"/>
+            </table>"#,
+        )
+        .unwrap();
+
+        assert_eq!(main_xml.name.as_deref(), Some("Some Heist"));
+        assert_eq!(main_xml.global_key.as_deref(), Some("SomeHeist"));
+        assert_eq!(main_xml.priority, Some(10));
+        assert_eq!(
+            main_xml.asset_updates,
+            Some(AssetUpdates {
+                provider: Some("modworkshop".to_string()),
+                id: Some("12345".to_string()),
+                version: Some("1.2".to_string()),
+            })
+        );
+        assert_eq!(
+            main_xml.dependencies,
+            vec![
+                MainXmlDependency {
+                    name: "BeardLib".to_string(),
+                    provider: Some("modworkshop".to_string()),
+                    id: Some("14924".to_string()),
+                    min_version: Some("4.0".to_string()),
+                },
+                MainXmlDependency {
+                    name: "SuperBLT".to_string(),
+                    provider: None,
+                    id: None,
+                    min_version: None,
+                },
+            ]
+        );
+
+        assert!(MainXml::parse("<table name=\"Broken\">").is_err());
+    }
+
+    #[test]
+    fn test_read_installed_metadata() {
+        let dir = test_dir("read_metadata");
+        let blt_root = dir.join("mods/Some Mod");
+        let overrides_root = dir.join("assets/mod_overrides/Some Mod Assets");
+        touch_all(
+            &dir,
+            &[
+                "mods/Some Mod/mod.txt",
+                "assets/mod_overrides/Some Mod Assets/main.xml",
+            ],
+        );
+        std::fs::write(
+            blt_root.join("mod.txt"),
+            r#"{ "name": "Some Mod", "version": "2", }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            overrides_root.join("main.xml"),
+            r#"<mod name="Some Mod Assets"><AssetUpdates id="7" version="3"/></mod>"#,
+        )
+        .unwrap();
+
+        let mut installed = InstalledMod::new(
+            7,
+            "Some Mod".to_string(),
+            ModRelease::default(),
+            InstallTarget::Mods,
+            blt_root,
+            Vec::new(),
+        );
+        installed.extra_roots.push(ModRoot {
+            target: InstallTarget::ModOverrides,
+            install_dir: overrides_root,
+        });
+        installed.read_metadata();
+
+        assert_eq!(installed.mod_txt.unwrap().version.as_deref(), Some("2"));
+        let main_xml = installed.main_xml.unwrap();
+        assert_eq!(main_xml.name.as_deref(), Some("Some Mod Assets"));
+        assert_eq!(
+            main_xml.asset_updates.unwrap().version.as_deref(),
+            Some("3")
+        );
+    }
+
+    #[test]
+    fn test_dependency_requirements() {
+        let mod_txt = ModTxt::parse(
+            r#"{ "name": "Some Mod", "dependencies": {
+                "Beard Lib": {},
+                "otherlib": { "download_url": "https://modworkshop.net/mod/555?tab=files" },
+            } }"#,
+        )
+        .unwrap();
+        let main_xml = MainXml::parse(
+            r#"<table name="Some Mod"><Dependencies><BeardLib/><Extra id="777"/></Dependencies></table>"#,
+        )
+        .unwrap();
+
+        let names = |requirements: &[Requirement]| -> Vec<(String, Option<u32>)> {
+            requirements
+                .iter()
+                .map(|r| (r.name.clone(), r.mod_id))
+                .collect()
+        };
+        // BLT mods need SuperBLT, `main.xml` mods need BeardLib, declared or not
+        assert_eq!(
+            names(&requirements(Some(&mod_txt), Some(&main_xml))),
+            vec![
+                ("SuperBLT".to_string(), None),
+                ("BeardLib".to_string(), Some(BEARDLIB_ID)),
+                ("otherlib".to_string(), Some(555)),
+                ("Extra".to_string(), Some(777)),
+            ]
+        );
+
+        // An archive that brings BeardLib along doesn't need it installed
+        let dir = test_dir("dependency_requirements");
+        let staged = dir.join("staging/1");
+        let game_dir = dir.join("game");
+        touch_all(&staged, &["mods/BeardLib/mod.txt", "Some Heist/main.xml"]);
+        std::fs::write(
+            staged.join("mods/BeardLib/mod.txt"),
+            r#"{ "name": "BeardLib" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            staged.join("Some Heist/main.xml"),
+            "<table name=\"Some Heist\"/>",
+        )
+        .unwrap();
+        let layout = InstallLayout::plan(&staged.join("mods"), &game_dir).unwrap();
+        assert_eq!(
+            names(&staged_requirements(1, &staged.join("mods"), &layout)),
+            vec![("SuperBLT".to_string(), None)]
+        );
+
+        let heist_layout = InstallLayout::plan(&staged.join("Some Heist"), &game_dir).unwrap();
+        let needed = staged_requirements(2, &staged.join("Some Heist"), &heist_layout);
+        assert_eq!(
+            names(&needed),
+            vec![("BeardLib".to_string(), Some(BEARDLIB_ID))]
+        );
+
+        // BeardLib installed by hand counts
+        let registry = ModRegistry::load_from(dir.join("pd2.json")).unwrap();
+        assert_eq!(
+            missing_requirements(needed.clone(), &registry, &game_dir).len(),
+            1
+        );
+        touch_all(&game_dir, &["mods/BeardLib/mod.txt"]);
+        assert!(missing_requirements(needed, &registry, &game_dir).is_empty());
+
+        assert_eq!(
+            modworkshop_id("https://modworkshop.net/mod/14924"),
+            Some(BEARDLIB_ID)
+        );
+        assert_eq!(modworkshop_id("https://example.com/mod/14924"), None);
+    }
+
+    #[test]
+    fn test_dependency_install_order() {
+        let needs = BTreeMap::from([(1, vec![2, 3]), (2, vec![3]), (3, vec![])]);
+        assert_eq!(install_order(1, &needs), Ok(vec![3, 2, 1]));
+        // Mods that aren't in the map don't need anything
+        assert_eq!(install_order(4, &needs), Ok(vec![4]));
+
+        let cyclic = BTreeMap::from([(1, vec![2]), (2, vec![3]), (3, vec![2])]);
+        assert_eq!(install_order(1, &cyclic), Err(vec![2, 3, 2]));
+    }
+
+    #[test]
+    fn test_mod_task_event_is_flat() {
+        let task = TaskRef {
+            task_id: 3,
+            mod_id: 12345,
+            kind: TaskKind::Install,
+        };
+
+        let downloading = serde_json::to_value(ModTaskEvent::Downloading(task)).unwrap();
+        assert_eq!(
+            downloading,
+            serde_json::json!({
+                "phase": "downloading",
+                "task_id": 3,
+                "mod_id": 12345,
+                "kind": "install",
+            })
+        );
+
+        let failed = serde_json::to_value(ModTaskEvent::Failed {
+            task,
+            error: InstallError::NoModRoot.payload(task.mod_id),
+        })
+        .unwrap();
+        assert_eq!(failed["phase"], "failed");
+        assert_eq!(failed["task_id"], 3);
+        assert_eq!(failed["error"]["code"], "MOD.NO_ROOT");
+    }
+
+    #[test]
+    fn test_download_progress_speed_and_eta() {
+        let progress = DownloadProgress::new(2_000, Some(10_000), Duration::from_secs(2));
+        assert_eq!(progress.bytes_per_second, 1_000);
+        assert_eq!(progress.eta_seconds, Some(8));
+
+        // Without a Content-Length we can't know how long is left
+        let unknown = DownloadProgress::new(2_000, None, Duration::from_secs(2));
+        assert_eq!(unknown.eta_seconds, None);
+
+        // Nothing has happened yet
+        let start = DownloadProgress::new(0, Some(10_000), Duration::ZERO);
+        assert_eq!(start.bytes_per_second, 0);
+        assert_eq!(start.eta_seconds, None);
+    }
+
+    /// How [`flaky_server`] answers a `Range` request
+    #[derive(Clone, Copy)]
+    enum RangeSupport {
+        /// Sends the whole body again as a `200`
+        Ignored,
+        /// Sends the rest of the body as a `206`
+        Honoured,
+        /// Sends the whole body as a `206`, like a host whose file changed under the range
+        WrongStart,
+    }
+
+    /// A tiny HTTP server standing in for ModWorkshop's file host.
+    ///
+    /// The first request gets the headers for the whole `body` but the connection is dropped half
+    /// way through. Later requests get whatever `support` says.
+    ///
+    /// # Returns
+    ///
+    /// The URL to download from, and the `Range` header of every request the server got.
+    async fn flaky_server(
+        body: Vec<u8>,
+        support: RangeSupport,
+    ) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mod.zip", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+
+        tokio::spawn(async move {
+            let mut first = true;
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&request).to_string();
+                let range = request
+                    .lines()
+                    .find_map(|line| {
+                        line.strip_prefix("range: ")
+                            .or(line.strip_prefix("Range: "))
+                    })
+                    .map(str::to_string);
+                seen.lock().unwrap().push(range.clone());
+
+                let requested = range
+                    .as_deref()
+                    .and_then(|r| r.strip_prefix("bytes="))
+                    .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+                let start = match support {
+                    RangeSupport::Ignored => None,
+                    RangeSupport::Honoured => requested,
+                    RangeSupport::WrongStart => requested.map(|_| 0),
+                };
+
+                let response = match start {
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {start}-{}/{}\r\nConnection: close\r\n\r\n",
+                        body.len() - start,
+                        body.len() - 1,
+                        body.len()
+                    ),
+                    None => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    ),
+                };
+                socket.write_all(response.as_bytes()).await.unwrap();
+
+                let content = &body[start.unwrap_or(0)..];
+                if first {
+                    // Drop the connection half way through
+                    first = false;
+                    socket
+                        .write_all(&content[..content.len() / 2])
+                        .await
+                        .unwrap();
+                } else {
+                    socket.write_all(content).await.unwrap();
+                }
+                let _ = socket.shutdown().await;
+            }
+        });
+
+        (url, ranges)
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_with_range() {
+        let dir = test_dir("download_resume");
+        let destination = dir.join("123.zip");
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let (url, ranges) = flaky_server(body.clone(), RangeSupport::Honoured).await;
+
+        let downloaded = download_to_file(
+            &reqwest::Client::new(),
+            &url,
+            &destination,
+            &TaskControl::default(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(downloaded.size, body.len() as u64);
+        assert_eq!(downloaded.file_name.as_deref(), Some("mod.zip"));
+        assert_eq!(downloaded.content_type, None);
+        assert_eq!(std::fs::read(&destination).unwrap(), body);
+        assert!(!partial_path(&destination).exists());
+
+        let ranges = ranges.lock().unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0], None);
+        assert_eq!(ranges[1].as_deref(), Some("bytes=5000-"));
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_without_range_support() {
+        let dir = test_dir("download_restart");
+        let destination = dir.join("123.zip");
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i % 241) as u8).collect();
+        let (url, ranges) = flaky_server(body.clone(), RangeSupport::Ignored).await;
+
+        download_to_file(
+            &reqwest::Client::new(),
+            &url,
+            &destination,
+            &TaskControl::default(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&destination).unwrap(), body);
+        assert_eq!(ranges.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_on_wrong_content_range() {
+        let dir = test_dir("download_wrong_range");
+        let destination = dir.join("123.zip");
+        let body: Vec<u8> = (0..10_000u32).map(|i| (i % 239) as u8).collect();
+        let (url, ranges) = flaky_server(body.clone(), RangeSupport::WrongStart).await;
+
+        download_to_file(
+            &reqwest::Client::new(),
+            &url,
+            &destination,
+            &TaskControl::default(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+
+        // The mismatched range is dropped, and the next attempt starts over
+        assert_eq!(std::fs::read(&destination).unwrap(), body);
+        let ranges = ranges.lock().unwrap();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[1].as_deref(), Some("bytes=5000-"));
+        assert_eq!(ranges[2], None);
+    }
+
+    /// Serves `body` slowly enough that a test can stop the download half way
+    async fn slow_server(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mod.zip", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            let mut buffer = [0u8; 1024];
+            let _ = socket.read(&mut buffer).await;
+
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            for chunk in body.chunks(1000) {
+                if socket.write_all(chunk).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        url
+    }
+
+    async fn stopped_download(name: &str, signal: TaskSignal) -> (PathBuf, InstallError) {
+        let dir = test_dir(name);
+        let destination = dir.join("123.zip");
+        let url = slow_server(vec![7u8; 100_000]).await;
+        let (sender, control) = TaskControl::channel();
+
+        let stopper = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sender.send(signal).unwrap();
+            // Keep the sender alive until the download has seen the signal
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+
+        let error = download_to_file(
+            &reqwest::Client::new(),
+            &url,
+            &destination,
+            &control,
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        stopper.abort();
+
+        assert!(!destination.exists());
+        (destination, error)
+    }
+
+    #[tokio::test]
+    async fn test_unknown_game_keys_are_rejected() {
+        for game in ["../../x", "pd2/../x", "", "unknown"] {
+            assert!(ModRegistry::load(game).is_err(), "{game} was accepted");
+            assert!(ProfileStore::load(game).is_err(), "{game} was accepted");
+            assert!(
+                StorageLayout::load(game).await.is_err(),
+                "{game} was accepted"
+            );
+        }
+        assert!(check_game_key(payday2::GAME_KEY).is_ok());
+    }
+
+    #[test]
+    fn test_storage_layout_stays_under_download_path() {
+        let download_path = PathBuf::from("some").join("downloads");
+        let layout = StorageLayout::new(&download_path, "pd2");
+        let root = download_path.join("pd2");
+
+        let dirs = [
+            layout.download_path(123, 456),
+            layout.archive_path(123, "zip"),
+            layout.staging_dir(123),
+            layout.backup_dir(123),
+            layout.disabled_dir(),
+        ];
+        for dir in &dirs {
+            assert!(dir.starts_with(&root), "{} escaped the root", dir.display());
+        }
+
+        // Extracting a mod must never touch its archive or another mod's files
+        assert!(!layout.staging_dir(123).starts_with(layout.archives_dir()));
+        assert_ne!(layout.staging_dir(123), layout.staging_dir(124));
+        // A new upload doesn't resume the download of the old one
+        assert_ne!(
+            layout.download_path(123, 456),
+            layout.download_path(123, 457)
+        );
+    }
+
+    #[test]
+    fn test_archive_cache_add_find_and_prune() {
+        let dir = test_dir("archive_cache");
+        let mut cache = ArchiveCache::load_from(dir.join("cache")).unwrap();
+
+        let first = dir.join("1.zip");
+        std::fs::write(&first, vec![1u8; 1000]).unwrap();
+        let first_hash = sha256_file(&first).unwrap();
+        let first = cache
+            .add(
+                &first,
+                first_hash.clone(),
+                1,
+                10,
+                "First".to_string(),
+                ModRelease::default(),
+            )
+            .unwrap();
+        assert_eq!(first.sha256, first_hash);
+        assert_eq!(first.file_name(), format!("1-10-{first_hash}.zip"));
+
+        let second = dir.join("2.zip");
+        std::fs::write(&second, vec![2u8; 500]).unwrap();
+        let second_hash = sha256_file(&second).unwrap();
+        let mut second = cache
+            .add(
+                &second,
+                second_hash,
+                2,
+                20,
+                "Second".to_string(),
+                ModRelease::default(),
+            )
+            .unwrap();
+        cache.save().unwrap();
+
+        // The index survives a reload, and lookups go by mod id and file id
+        let mut cache = ArchiveCache::load_from(dir.join("cache")).unwrap();
+        assert_eq!(cache.total_size(), 1500);
+        assert_eq!(cache.find(1, 10), Some(&first));
+        assert!(cache.find(1, 11).is_none());
+        assert_eq!(cache.latest(2), Some(&second));
+        assert!(cache.verify(&second));
+
+        // A damaged archive is thrown out
+        second = cache.latest(2).cloned().unwrap();
+        std::fs::write(cache.path_of(&second), b"garbage").unwrap();
+        assert!(!cache.verify(&second));
+        assert!(cache.latest(2).is_none());
+        assert!(!cache.path_of(&second).exists());
+
+        let report = cache.prune(None, Some(0));
+        assert_eq!(report.removed, 1);
+        assert_eq!(report.freed, 1000);
+        assert_eq!(cache.total_size(), 0);
+        assert!(!cache.path_of(&first).exists());
+    }
+
+    #[test]
+    fn test_archive_format_from_path() {
+        let format = |name: &str| ArchiveFormat::from_path(Path::new(name));
+        assert_eq!(
+            format("https://example.com/1.ZIP"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(format("mod.7z"), Some(ArchiveFormat::SevenZip));
+        assert_eq!(format("mod.rar"), Some(ArchiveFormat::Rar));
+        assert_eq!(format("mod.tar.gz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(format("mod.tgz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(format("mod.exe"), None);
+        assert_eq!(format("mod"), None);
+    }
+
+    #[test]
+    fn test_archive_format_detected_from_content() {
+        let dir = test_dir("detect_format");
+
+        for (name, expected) in [
+            ("mod.zip", ArchiveFormat::Zip),
+            ("mod.7z", ArchiveFormat::SevenZip),
+            ("mod.tar.gz", ArchiveFormat::TarGz),
+            ("mod.rar", ArchiveFormat::Rar),
+        ] {
+            // The name says nothing about the format, like a download from `/download?file=123`
+            let download = dir.join("123.download");
+            std::fs::copy(fixture(name), &download).unwrap();
+            assert_eq!(ArchiveFormat::detect(&download), Some(expected), "{name}");
+
+            // What the server says doesn't matter when the contents are recognised
+            let mislabelled = DownloadedFile {
+                size: 0,
+                file_name: Some("mod.exe".to_string()),
+                content_type: Some("application/zip".to_string()),
+            };
+            assert_eq!(
+                ArchiveFormat::detect_download(&download, &mislabelled),
+                Some(expected),
+                "{name}"
+            );
+        }
+
+        // Unknown contents fall back to the file name, then to Content-Type
+        let unknown = dir.join("123.download");
+        std::fs::write(&unknown, b"not an archive").unwrap();
+        assert_eq!(ArchiveFormat::detect(&unknown), None);
+
+        let mut download = DownloadedFile {
+            size: 14,
+            file_name: Some("SomeMod.7z".to_string()),
+            content_type: Some("application/zip".to_string()),
+        };
+        assert_eq!(
+            ArchiveFormat::detect_download(&unknown, &download),
+            Some(ArchiveFormat::SevenZip)
+        );
+        download.file_name = Some("download".to_string());
+        assert_eq!(
+            ArchiveFormat::detect_download(&unknown, &download),
+            Some(ArchiveFormat::Zip)
+        );
+        download.content_type = Some("application/octet-stream".to_string());
+        assert_eq!(ArchiveFormat::detect_download(&unknown, &download), None);
+
+        assert_eq!(
+            ArchiveFormat::from_content_type("application/x-rar-compressed; charset=binary"),
+            Some(ArchiveFormat::Rar)
+        );
+    }
+
+    #[test]
+    fn test_download_file_name_from_headers() {
+        assert_eq!(
+            content_disposition_file_name("attachment; filename=\"Some Mod.zip\"").as_deref(),
+            Some("Some Mod.zip")
+        );
+        assert_eq!(
+            content_disposition_file_name(
+                "attachment; filename=\"fallback.zip\"; filename*=UTF-8''Some%20Mod.7z"
+            )
+            .as_deref(),
+            Some("Some Mod.7z")
+        );
+        assert_eq!(content_disposition_file_name("inline"), None);
+
+        assert_eq!(
+            url_file_name("https://storage.modworkshop.net/mods/files/mod.rar?token=abc#x")
+                .as_deref(),
+            Some("mod.rar")
+        );
+        assert_eq!(url_file_name("https://example.com/"), None);
+        assert_eq!(url_file_name("not a url"), None);
+    }
+
+    #[test]
+    fn test_enclosed_path_rejects_escapes() {
+        assert_eq!(
+            enclosed_path("Wrapper\\SomeMod/./mod.txt"),
+            Some(PathBuf::from("Wrapper").join("SomeMod").join("mod.txt"))
+        );
+        assert_eq!(enclosed_path("../mods/evil.lua"), None);
+        assert_eq!(enclosed_path("/etc/passwd"), None);
+        assert_eq!(enclosed_path(""), None);
+    }
+
+    #[test]
+    fn test_extract_every_archive_format() {
+        for name in ["mod.zip", "mod.7z", "mod.tar.gz"] {
+            assert_extracts_fixture(name);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs unrar, 7-Zip or bsdtar to be installed, run with --ignored"]
+    fn test_extract_rar() {
+        assert_extracts_fixture("mod.rar");
+    }
+
+    #[test]
+    fn test_rar_entries_are_checked_before_extracting() {
+        let seven_zip = "Path = /tmp/mod.rar\nType = Rar\n\n----------\nPath = Wrapper/SomeMod\nFolder = +\n\nPath = ../evil.lua\nFolder = -\n";
+        assert_eq!(
+            rar_entries("7z", seven_zip),
+            vec!["Wrapper/SomeMod".to_string(), "../evil.lua".to_string()]
+        );
+        assert_eq!(
+            rar_entries("unrar", "Wrapper\\SomeMod\\mod.txt\r\n\r\n/etc/passwd\r\n"),
+            vec![
+                "Wrapper\\SomeMod\\mod.txt".to_string(),
+                "/etc/passwd".to_string()
+            ]
+        );
+
+        assert!(!escapes("./Wrapper/SomeMod/"));
+        assert!(!escapes("Wrapper\\SomeMod\\mod.txt"));
+        assert!(escapes("../evil.lua"));
+        assert!(escapes("Wrapper\\..\\..\\evil.lua"));
+        assert!(escapes("/etc/passwd"));
+    }
+
+    fn assert_extracts_fixture(name: &str) {
+        let archive = fixture(name);
+        let format = ArchiveFormat::from_path(&archive).unwrap();
+        let dir = test_dir(&format!("extract_{}", format.extension()));
+
+        format.validate(&archive).unwrap();
+        format.extract(&archive, &dir).unwrap();
+
+        let [(root, target)] = find_mod_roots(&dir).try_into().unwrap();
+        assert_eq!(root, dir.join("Wrapper").join("SomeMod"), "{name}");
+        assert_eq!(target, InstallTarget::Mods, "{name}");
+        assert_eq!(
+            std::fs::read_to_string(root.join("lua").join("main.lua")).unwrap(),
+            "log(\"Some Mod loaded\")\n",
+            "{name}"
+        );
+    }
+
+    #[test]
+    fn test_validate_zip_catches_truncated_archives() {
+        let dir = test_dir("validate_zip");
+        let path = dir.join("mod.zip");
+        let contents: Vec<u8> = (0..50_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        write_zip(
+            &path,
+            &[("SomeMod/mod.txt", b"{}"), ("SomeMod/big.bin", &contents)],
+        );
+        validate_zip(&path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+
+        // Cut off the end, taking the central directory with it
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let error = validate_zip(&path).unwrap_err();
+        assert_eq!(error.code(), "MOD.INTEGRITY");
+
+        // Keep the central directory but lose file data
+        let mut missing_data = bytes[..100].to_vec();
+        missing_data.extend_from_slice(&bytes[bytes.len() - 200..]);
+        std::fs::write(&path, &missing_data).unwrap();
+        assert_eq!(validate_zip(&path).unwrap_err().code(), "MOD.INTEGRITY");
+    }
+
+    #[test]
+    fn test_verify_file_checks_size_and_hash() {
+        let dir = test_dir("verify_file");
+        let path = dir.join("mod.zip");
+        std::fs::write(&path, b"hello").unwrap();
+        let sha256 = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        let expected = ExpectedFile {
+            size: Some(5),
+            hash: Some(sha256.to_uppercase()),
+        };
+        assert_eq!(verify_file(&path, &expected).unwrap(), sha256);
+
+        let wrong_size = ExpectedFile {
+            size: Some(6),
+            ..expected.clone()
+        };
+        assert!(matches!(
+            verify_file(&path, &wrong_size),
+            Err(InstallError::Integrity(_))
+        ));
+
+        let wrong_hash = ExpectedFile {
+            size: None,
+            hash: Some("0".repeat(64)),
+        };
+        assert!(matches!(
+            verify_file(&path, &wrong_hash),
+            Err(InstallError::Integrity(_))
+        ));
+
+        let md5 = ExpectedFile {
+            size: None,
+            hash: Some("5d41402abc4b2a76b9719d911017c592".to_string()),
+        };
+        assert_eq!(verify_file(&path, &md5).unwrap(), sha256);
+        let wrong_md5 = ExpectedFile {
+            size: None,
+            hash: Some("0".repeat(32)),
+        };
+        assert!(matches!(
+            verify_file(&path, &wrong_md5),
+            Err(InstallError::Integrity(_))
+        ));
+
+        // A download we can't check isn't installed
+        for hash in [None, Some("not a hash".to_string()), Some("ab".repeat(20))] {
+            let unverifiable = ExpectedFile { size: None, hash };
+            assert!(matches!(
+                verify_file(&path, &unverifiable),
+                Err(InstallError::Integrity(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_paused_download_keeps_part_file() {
+        let (destination, error) = stopped_download("download_pause", TaskSignal::Pause).await;
+
+        assert!(matches!(error, InstallError::Paused));
+        assert!(partial_path(&destination).exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_removes_part_file() {
+        let (destination, error) = stopped_download("download_cancel", TaskSignal::Cancel).await;
+
+        assert!(matches!(error, InstallError::Cancelled));
+        assert!(!partial_path(&destination).exists());
+    }
+
+    /// How a task run by [`scripted_queue`] ends, once the test says so
+    #[derive(Clone, Copy)]
+    enum Outcome {
+        Done,
+        Fail,
+        Panic,
+    }
+
+    /// A queue whose tasks run (listening for pause and cancel) until `outcomes` has an outcome
+    /// for their mod
+    fn scripted_queue(max_concurrent: usize) -> (TaskQueue, Arc<Mutex<BTreeMap<u32, Outcome>>>) {
+        let outcomes: Arc<Mutex<BTreeMap<u32, Outcome>>> = Arc::default();
+        let script = outcomes.clone();
+        let runner: TaskRunner = Arc::new(move |task: TaskEmitter| {
+            let script = script.clone();
+            Box::pin(async move {
+                loop {
+                    task.control().check()?;
+                    let outcome = script.lock().unwrap().get(&task.task().mod_id).copied();
+                    match outcome {
+                        Some(Outcome::Done) => return Ok(()),
+                        Some(Outcome::Fail) => return Err(InstallError::NotInstalled),
+                        Some(Outcome::Panic) => panic!("scripted panic"),
+                        None => tokio::time::sleep(Duration::from_millis(5)).await,
+                    }
+                }
+            })
+        });
+        (TaskQueue::with_runner(max_concurrent, runner), outcomes)
+    }
+
+    fn install_tasks(mod_ids: &[u32]) -> Vec<TaskEmitter> {
+        mod_ids
+            .iter()
+            .map(|mod_id| TaskEmitter::detached(*mod_id, TaskKind::Install))
+            .collect()
+    }
+
+    fn queued_task(queue: &TaskQueue, task_id: u64) -> QueuedTask {
+        queue
+            .list()
+            .into_iter()
+            .find(|t| t.task.task_id == task_id)
+            .expect("task should be in the queue")
+    }
+
+    /// Waits for task `task_id` to get to `status`
+    async fn wait_for_status(queue: &TaskQueue, task_id: u64, status: TaskStatus) -> QueuedTask {
+        for _ in 0..400 {
+            let task = queued_task(queue, task_id);
+            if task.status == status {
+                return task;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("Task {task_id} never got to {status:?}: {:?}", queue.list());
+    }
+
+    #[tokio::test]
+    async fn test_queue_concurrency_limit() {
+        let (queue, outcomes) = scripted_queue(1);
+        let first = queue.enqueue_tasks(install_tasks(&[1])).await.unwrap()[0];
+        let second = queue.enqueue_tasks(install_tasks(&[2])).await.unwrap()[0];
+
+        wait_for_status(&queue, first, TaskStatus::Running).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queued_task(&queue, second).status, TaskStatus::Queued);
+
+        // Another task for a mod that has one is turned away
+        assert!(queue.enqueue_tasks(install_tasks(&[1])).await.is_err());
+
+        outcomes.lock().unwrap().insert(1, Outcome::Done);
+        wait_for_status(&queue, first, TaskStatus::Done).await;
+        wait_for_status(&queue, second, TaskStatus::Running).await;
+
+        outcomes.lock().unwrap().insert(2, Outcome::Done);
+        wait_for_status(&queue, second, TaskStatus::Done).await;
+    }
+
+    #[tokio::test]
+    async fn test_queue_pause_resume_and_cancel() {
+        let (queue, _outcomes) = scripted_queue(1);
+        let first = queue.enqueue_tasks(install_tasks(&[1])).await.unwrap()[0];
+        let second = queue.enqueue_tasks(install_tasks(&[2])).await.unwrap()[0];
+        wait_for_status(&queue, first, TaskStatus::Running).await;
+
+        // A queued task is paused right away, a running one once it notices
+        queue.pause(second).unwrap();
+        assert_eq!(queued_task(&queue, second).status, TaskStatus::Paused);
+        queue.pause(first).unwrap();
+        wait_for_status(&queue, first, TaskStatus::Paused).await;
+
+        queue.resume(first).await.unwrap();
+        wait_for_status(&queue, first, TaskStatus::Running).await;
+        queue.cancel(first).unwrap();
+        wait_for_status(&queue, first, TaskStatus::Cancelled).await;
+        assert!(queue.resume(first).await.is_err());
+
+        // The freed slot goes to the resumed task
+        queue.resume(second).await.unwrap();
+        wait_for_status(&queue, second, TaskStatus::Running).await;
+        queue.cancel(second).unwrap();
+        wait_for_status(&queue, second, TaskStatus::Cancelled).await;
+
+        queue.clear_finished();
+        assert!(queue.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_fails_tasks_waiting_for_a_failed_task() {
+        let (queue, outcomes) = scripted_queue(2);
+        let tasks = queue
+            .enqueue_tasks(install_tasks(&[1, 2, 3]))
+            .await
+            .unwrap();
+        wait_for_status(&queue, tasks[0], TaskStatus::Running).await;
+        // Chained tasks wait for the one before them, even with a free slot
+        assert_eq!(queued_task(&queue, tasks[1]).status, TaskStatus::Queued);
+
+        outcomes.lock().unwrap().insert(1, Outcome::Fail);
+        wait_for_status(&queue, tasks[0], TaskStatus::Failed).await;
+        for task_id in &tasks[1..] {
+            let task = queued_task(&queue, *task_id);
+            assert_eq!(task.status, TaskStatus::Failed);
+            assert_eq!(task.error.unwrap().code, "MOD.DEPENDENCY_FAILED");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_frees_the_slot_of_a_panicked_task() {
+        let (queue, outcomes) = scripted_queue(1);
+        let first = queue.enqueue_tasks(install_tasks(&[1])).await.unwrap()[0];
+        let second = queue.enqueue_tasks(install_tasks(&[2])).await.unwrap()[0];
+        wait_for_status(&queue, first, TaskStatus::Running).await;
+
+        outcomes.lock().unwrap().insert(1, Outcome::Panic);
+        let panicked = wait_for_status(&queue, first, TaskStatus::Failed).await;
+        assert_eq!(panicked.error.unwrap().code, "TASK.PANICKED");
+        wait_for_status(&queue, second, TaskStatus::Running).await;
+    }
+
+    #[tokio::test]
+    async fn test_queue_claim_is_exclusive() {
+        let (queue, _outcomes) = scripted_queue(1);
+        let plan = TaskEmitter::detached(5, TaskKind::Plan);
+        let claim = queue.claim(&plan).unwrap();
+        assert_eq!(
+            queued_task(&queue, plan.task().task_id).status,
+            TaskStatus::Running
+        );
+
+        assert!(queue.enqueue_tasks(install_tasks(&[5])).await.is_err());
+        assert!(matches!(
+            queue.claim(&TaskEmitter::detached(5, TaskKind::Plan)),
+            Err(InstallError::AlreadyQueued(5))
+        ));
+
+        drop(claim);
+        assert!(queue.list().is_empty());
+        let task_id = queue.enqueue_tasks(install_tasks(&[5])).await.unwrap()[0];
+        assert!(queue
+            .claim(&TaskEmitter::detached(5, TaskKind::Plan))
+            .is_err());
+        queue.cancel(task_id).unwrap();
+    }
 }
@@ -52,7 +52,8 @@ function ModItem({ mod, status }: ModItemProps) {
                   window: appWindow,
                 });
               } catch (e) {
-                toast.error("An error occurred", {
+                // This only queues the download, errors while it runs are shown by the `mod_task` listener
+                toast.error("Failed to queue the download", {
                   description: `Details: ${e}`,
                 });
               }
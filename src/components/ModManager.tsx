@@ -9,6 +9,7 @@ import {
   GameInformation,
   ModInfo,
   ModMetaInfo,
+  ModTaskEvent,
 } from "../lib/types";
 import ModItem from "./ModItem";
 import { Popover, PopoverContent, PopoverTrigger } from "./ui/popover";
@@ -28,6 +29,58 @@ import {
   CommandShortcut,
 } from "./ui/command";
 import ModManagerSearch from "./ModManagerSearch";
+function formatBytes(bytes: number): string {
+  const units = ["B", "KB", "MB", "GB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit++;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+// The text shown on a mod's button (and its toast) for each phase of a task
+function taskStatusMessage(task: ModTaskEvent): string {
+  switch (task.phase) {
+    case "queued":
+      return "Queued";
+    case "started":
+      return task.kind === "uninstall" ? "Uninstalling..." : "Starting...";
+    case "downloading":
+      return "Downloading...";
+    case "progress": {
+      const speed = `${formatBytes(task.bytes_per_second ?? 0)}/s`;
+      if (!task.total) {
+        return `Downloading... ${formatBytes(task.received ?? 0)} (${speed})`;
+      }
+      const percent = Math.floor(((task.received ?? 0) / task.total) * 100);
+      const eta = task.eta_seconds != null ? `, ${task.eta_seconds}s left` : "";
+      return `Downloading... ${percent}% (${speed}${eta})`;
+    }
+    case "writing":
+      return "Saving...";
+    case "conflicts":
+      return `Overwrites ${task.conflicts?.length ?? 0} files of other mods`;
+    case "missing_dependencies":
+      return `Needs ${task.dependencies?.map((d) => d.name).join(", ")}`;
+    case "installing":
+      return "Finishing up...";
+    case "removing":
+      return `Removing... (${task.removed}/${task.total})`;
+    case "done":
+      if (task.kind === "uninstall") return "Uninstalled";
+      if (task.kind === "plan") return "Ready to install";
+      return task.kind === "update" ? "Updated" : "Downloaded";
+    case "paused":
+      return "Paused";
+    case "cancelled":
+      return "Cancelled";
+    case "failed":
+      return "Failed";
+  }
+}
+
 function ModManager() {
   // State variables
   const [isLoading, setIsLoading] = useState(true);
@@ -35,7 +88,6 @@ function ModManager() {
   const [status, setStatus] = useState<Map<number, string>>(new Map());
   const [hasErrored, setHasErrored] = useState(false);
   const [errorMessage, setErrorMessage] = useState("Unknown");
-  const [notifyEventHandled, setNotifyEventHandled] = useState(false);
   const [settings, setSettings] = useState<AppSettings>();
   const [selectedGame, setSelectedGame] = useState<GameInformation | null>(
     null
@@ -72,81 +124,39 @@ function ModManager() {
     }
 
     async function setupListeners() {
-      const modDownloadStartedListener = await listen(
-        "mod_download_started",
-        (event) => {
-          const id = event.payload as number;
-          setStatus((prevStatus) =>
-            new Map(prevStatus).set(id, "Downloading...")
-          );
-        }
-      );
+      // Every task has its own toast (keyed by task id), which is updated as the task progresses
+      return await listen<ModTaskEvent>("mod_task", (event) => {
+        const task = event.payload;
+        const message = taskStatusMessage(task);
+        const toastId = `mod-task-${task.task_id}`;
 
-      const modWritingListener = await listen("mod_writing", (event) => {
-        const id = event.payload as number;
-        setStatus((prevStatus) => new Map(prevStatus).set(id, "Saving..."));
-      });
+        setStatus((prevStatus) => new Map(prevStatus).set(task.mod_id, message));
 
-      const modFinishingUpListener = await listen(
-        "mod_finishing_up",
-        (event) => {
-          const id = event.payload as number;
-          setStatus((prevStatus) =>
-            new Map(prevStatus).set(id, "Finishing up...")
-          );
+        if (task.phase === "failed") {
+          toast.error(`Error: ${task.error?.message}`, {
+            id: toastId,
+            description: `An error occurred during mod processing. (${task.error?.code})`,
+          });
+        } else if (task.phase === "done") {
+          toast.success(message, { id: toastId });
+        } else {
+          toast.info(message, { id: toastId });
         }
-      );
-
-      const modDoneListener = await listen("mod_done", (event) => {
-        const id = event.payload as number;
-        setStatus((prevStatus) => new Map(prevStatus).set(id, "Downloaded"));
-      });
-
-      const modErrorListener = await listen("mod_error", (event) => {
-        if (notifyEventHandled) return;
-        setNotifyEventHandled(true);
-        setStatus((prevStatus) =>
-          new Map(prevStatus).set(-1, `Error: ${event.payload}`)
-        );
       });
-
-      // Clean up listeners when component unmounts
-      return () => {
-        listen("mod_download_started", modDownloadStartedListener);
-        listen("mod_writing", modWritingListener);
-        listen("mod_finishing_up", modFinishingUpListener);
-        listen("mod_done", modDoneListener);
-        listen("mod_error", modErrorListener);
-      };
     }
 
     // Initialize listeners and load mods
-    setupListeners();
+    const unlisten = setupListeners();
     loadMods();
     loadSettings();
 
     // Cleanup listeners on component unmount
     return () => {
-      // cleanupListeners();
+      unlisten.then((stop) => stop());
       console.log("Done!");
     };
   }, []);
 
-  // Effect to handle status updates and show toasts
-  useEffect(() => {
-    status.forEach((message, id) => {
-      if (id === -1) {
-        toast.error(message, {
-          description: "An error occurred during mod processing.",
-        });
-      } else if (message === "Downloaded") {
-        toast.success("Mod downloaded!");
-      } else {
-        toast.info(message);
-      }
-    });
-  }, [status]); // Run when status changes
-
   return (
     <div className="">
       {/* Loader component displayed when loading */}
@@ -1,7 +1,9 @@
 export interface AppSettings {
     theme: "Dark" | "Light",
     download_path: string,
-    show_debug_options: boolean
+    show_debug_options: boolean,
+    active_profiles: Record<string, string>,
+    max_concurrent_downloads: number
 }
 
 export interface GameInformation {
@@ -31,4 +33,128 @@ export interface ModInfo {
   mod_data: ModDataInfo
   mod_meta: ModMetaInfo
 };
-  
\ No newline at end of file
+
+// Error details of a failed mod task
+export interface ModError {
+  code: string;
+  message: string;
+  mod_id: number;
+}
+
+// Payload of the `mod_task` event, one per phase of a download/install/uninstall
+export interface ModTaskEvent {
+  phase:
+    | "queued"
+    | "started"
+    | "downloading"
+    | "progress"
+    | "writing"
+    | "conflicts"
+    | "missing_dependencies"
+    | "installing"
+    | "removing"
+    | "done"
+    | "paused"
+    | "cancelled"
+    | "failed";
+  task_id: number;
+  mod_id: number;
+  kind: "install" | "update" | "uninstall" | "plan";
+  // Only set when `phase` is "progress"
+  received?: number;
+  total?: number | null;
+  bytes_per_second?: number;
+  eta_seconds?: number | null;
+  // Only set when `phase` is "removing"
+  removed?: number;
+  // Only set when `phase` is "conflicts"
+  conflicts?: FileConflict[];
+  // Only set when `phase` is "missing_dependencies"
+  dependencies?: Requirement[];
+  // Only set when `phase` is "failed"
+  error?: ModError;
+}
+
+// A task in the download queue, as returned by `list_tasks`
+export interface QueuedTask {
+  task_id: number;
+  mod_id: number;
+  kind: "install" | "update" | "uninstall" | "plan";
+  status: "queued" | "running" | "paused" | "cancelled" | "done" | "failed";
+  error: ModError | null;
+}
+
+// An archive in the download cache, see `get_cache_info`
+export interface CachedArchive {
+  mod_id: number;
+  file_id: number;
+  sha256: string;
+  ext: string;
+  name: string;
+  version: string | null;
+  size: number;
+  cached_at: number;
+  last_used: number;
+}
+
+export interface CacheInfo {
+  archives: CachedArchive[];
+  total_size: number;
+}
+
+// A path more than one installed mod writes, see `get_conflicts`. Assets are relative to the
+// mod_overrides roots, files to the game directory.
+export interface FileConflict {
+  kind: "asset" | "file";
+  path: string;
+  mods: number[];
+}
+
+// A mod another mod needs. `mod_id` is null for mods that can't be downloaded from ModWorkshop
+export interface Requirement {
+  name: string;
+  mod_id: number | null;
+}
+
+export type InstallTarget = "Mods" | "ModOverrides" | "Game";
+
+export type ModType =
+  | "blt_mod"
+  | "mod_override"
+  | "beard_lib_mod"
+  | "beard_lib_map"
+  | "game_files";
+
+// Where a file of an archive would be installed, see `plan_install`
+export interface PlannedFile {
+  source: string;
+  destination: string;
+}
+
+export interface PlannedRoot {
+  source: string;
+  target: InstallTarget;
+  mod_type: ModType;
+  install_dir: string;
+  files: PlannedFile[];
+}
+
+export interface InstallLayout {
+  kind: "mod_roots" | "game_tree";
+  roots: PlannedRoot[];
+}
+
+// What installing a mod would do, returned by `plan_install` for the user to confirm
+export interface InstallPlan {
+  mod_id: number;
+  name: string;
+  version: string | null;
+  sha256: string;
+  layout: InstallLayout;
+  // Files in the game directory that would be replaced
+  overwrites: string[];
+  // Paths that other installed mods write too
+  conflicts: FileConflict[];
+  // Mods it needs that aren't installed, see `plan_dependencies`
+  missing_dependencies: Requirement[];
+}